@@ -0,0 +1,39 @@
+#![allow(clippy::needless_return)]
+
+// The speed computed every cycle from a reading, over the default curve and a
+// long one:
+//
+//     cargo bench --bench curve
+#[path = "../src/steps.rs"]
+mod steps;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use steps::{clamp_speed, evaluate_curve, quantize_speed, TempSpeedPair};
+
+fn curve(steps: i16) -> Vec<TempSpeedPair> {
+    return (1..=steps).map(|step| TempSpeedPair {
+        temperature: 30 + step * 60 / steps,
+        fan_speed: (step * 100 / steps) as u8,
+    }).collect();
+}
+
+fn speed(step: &[TempSpeedPair], temperature: f32) -> u8 {
+    let speed = clamp_speed(evaluate_curve(step, temperature), Some(10), Some(90));
+    return quantize_speed(speed, 10);
+}
+
+fn bench_curve(criterion: &mut Criterion) {
+    for steps in [4, 32] {
+        let step = curve(steps);
+        criterion.bench_function(&format!("curve of {} steps", steps), |bencher| {
+            let mut temperature = 25.0;
+            bencher.iter(|| {
+                temperature = if temperature >= 95.0 { 25.0 } else { temperature + 0.1 };
+                return speed(black_box(&step), black_box(temperature));
+            });
+        });
+    }
+}
+
+criterion_group!(benches, bench_curve);
+criterion_main!(benches);
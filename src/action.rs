@@ -0,0 +1,155 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use crate::{hooks, inhibit, FanConfig, SharedState};
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Poweroff,
+    Reboot,
+    DisplayToggle,
+    Profile(String),
+}
+
+#[derive(Debug)]
+pub enum ActionError {
+    UnknownProfile(String),
+}
+
+impl std::error::Error for ActionError {}
+
+impl std::fmt::Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ActionError::UnknownProfile(profile) => write!(f, "Action refers to unknown profile '{}'", profile),
+        }
+    }
+}
+
+// A profile the button or the remote switches to has to exist, the fans would
+// otherwise have no configuration to run on.
+pub fn validate<'a>(actions: impl Iterator<Item = &'a Action>, profiles: Option<&BTreeMap<String, FanConfig>>) -> Result<(), ActionError> {
+    for action in actions {
+        if let Action::Profile(profile) = action {
+            if profile != "auto" && !profiles.is_some_and(|profiles| profiles.contains_key(profile)) {
+                return Err(ActionError::UnknownProfile(profile.clone()));
+            }
+        }
+    }
+    return Ok(());
+}
+
+impl Action {
+    fn command(&self) -> &'static str {
+        return match self {
+            Action::Reboot => "reboot",
+            _ => "poweroff",
+        };
+    }
+
+    fn shut_down(&self, reason: &str, state: &SharedState) -> Result<(), Box<dyn std::error::Error>> {
+        let command = self.command();
+        state.emit(hooks::Event::Shutdown { action: command, reason });
+        state.shutting_down.store(true, Ordering::SeqCst);
+        state.led_changed.notify_waiters();
+        if let (Action::Poweroff, Some(mcu)) = (self, &state.mcu) {
+            if let Err(error) = mcu.signal_poweroff() {
+                log::warn!("Failed to tell the fan controller to cut the power: {}", error);
+            }
+        }
+        let result = match self {
+            Action::Reboot => state.system.reboot(),
+            _ => state.system.poweroff(),
+        };
+        if result.is_err() {
+            state.shutting_down.store(false, Ordering::SeqCst);
+            state.led_changed.notify_waiters();
+        }
+        return result;
+    }
+
+    // The reason tells the shutdown hook what asked for it.
+    pub fn run(&self, reason: &str, state: &SharedState) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            // A press of the button or the remote waits for the inhibitors of
+            // other programs, a backup in progress for one, which only logind
+            // knows about. The temperature and the battery do not wait.
+            Action::Poweroff | Action::Reboot if reason == "button" || reason == "ir" => {
+                if state.shutdown_pending.swap(true, Ordering::SeqCst) {
+                    log::info!("A {} is already pending", self.command());
+                    return Ok(());
+                }
+                let action = self.clone();
+                let reason = String::from(reason);
+                let state = Arc::clone(state);
+                tokio::task::spawn_local(async move {
+                    if state.system.logind() && !inhibit::wait_for_inhibitors(&state.inhibit, action.command(), &state).await {
+                        return;
+                    }
+                    // Another press may try again.
+                    if let Err(error) = action.shut_down(&reason, &state) {
+                        log::error!("Failed to {}: {}", action.command(), error);
+                        state.shutdown_pending.store(false, Ordering::SeqCst);
+                    }
+                });
+            },
+            Action::Poweroff | Action::Reboot => self.shut_down(reason, state)?,
+            Action::DisplayToggle => {
+                state.display_off.fetch_xor(true, Ordering::SeqCst);
+                state.display_toggled.notify_waiters();
+            },
+            Action::Profile(profile) => {
+                *state.profile_override.lock().unwrap() = match profile.as_str() {
+                    "auto" => None,
+                    _ => Some(profile.clone()),
+                };
+                state.speed_override_changed.notify_waiters();
+            },
+        };
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::Recording;
+    use crate::State;
+
+    fn state(recording: &Recording) -> SharedState {
+        return Arc::new(State {
+            hooks: toml::from_str("on_shutdown = \"/etc/argononed/shutdown\"").unwrap(),
+            system: Box::new(recording.clone()),
+            ..State::default()
+        });
+    }
+
+    #[test]
+    fn poweroff_runs_the_hook_then_powers_off() {
+        let recording = Recording::default();
+        let state = state(&recording);
+        Action::Poweroff.run("temperature", &state).unwrap();
+        assert_eq!(recording.calls(), [
+            "/etc/argononed/shutdown ARGONONE_EVENT=shutdown ARGONONE_ACTION=poweroff ARGONONE_REASON=temperature",
+            "poweroff",
+        ]);
+        assert!(state.shutting_down.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn reboot_reboots() {
+        let recording = Recording::default();
+        Action::Reboot.run("battery", &state(&recording)).unwrap();
+        assert_eq!(recording.calls()[1], "reboot");
+    }
+
+    #[test]
+    fn failed_poweroff_is_not_shutting_down() {
+        let recording = Recording { fail: true, ..Recording::default() };
+        let state = state(&recording);
+        assert!(Action::Poweroff.run("temperature", &state).is_err());
+        assert!(!state.shutting_down.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,159 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+const DEFAULT_TEMPLATE: &str = r#"{"host": "{host}", "event": "{event}", "message": "{message}"}"#;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Alert {
+    Critical,
+    Stall,
+    I2cErrors,
+    LowBattery,
+}
+
+impl Alert {
+    fn name(self) -> &'static str {
+        return match self {
+            Alert::Critical => "critical",
+            Alert::Stall => "stall",
+            Alert::I2cErrors => "i2c_errors",
+            Alert::LowBattery => "low_battery",
+        };
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AlertsConfig {
+    url: String,
+    events: Option<Vec<Alert>>,
+    template: Option<String>,
+    min_interval: Option<u64>,
+    low_battery_charge: Option<f32>,
+}
+
+#[derive(Debug)]
+pub enum AlertError {
+    InvalidUrl(String),
+    Status(String),
+}
+
+impl std::error::Error for AlertError {}
+
+impl std::fmt::Display for AlertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AlertError::InvalidUrl(url) => write!(f, "Invalid webhook URL '{}', expected http:// or https://", url),
+            AlertError::Status(status) => write!(f, "The webhook answered '{}'", status),
+        }
+    }
+}
+
+// Every kind of alert is sent at most once every min_interval seconds, so a
+// temperature hovering around the critical one does not flood the channel.
+#[derive(Default)]
+pub struct Alerts {
+    config: Option<AlertsConfig>,
+    sent_at: Mutex<BTreeMap<Alert, Instant>>,
+}
+
+pub fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } < 0 {
+        return String::from("unknown");
+    }
+    let length = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+    return String::from_utf8_lossy(&buffer[..length]).into_owned();
+}
+
+// Values are escaped as JSON strings, the template provides the quotes.
+fn render(template: &str, alert: Alert, message: &str) -> String {
+    let escape = |value: &str| {
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        return String::from(&quoted[1..quoted.len() - 1]);
+    };
+    return template
+        .replace("{host}", &escape(&hostname()))
+        .replace("{event}", alert.name())
+        .replace("{message}", &escape(message));
+}
+
+// Plain HTTP is spoken directly, HTTPS is left to curl as the daemon has no
+// TLS of its own.
+async fn post(url: &str, body: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if url.starts_with("https://") {
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", "10", "--header", "Content-Type: application/json", "--data-binary", body, url])
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(Box::new(AlertError::Status(String::from_utf8_lossy(&output.stderr).trim().to_string())));
+        }
+        return Ok(());
+    }
+    let rest = url.strip_prefix("http://").ok_or_else(|| AlertError::InvalidUrl(String::from(url)))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let address = match authority.contains(':') {
+        true => String::from(authority),
+        false => format!("{}:80", authority),
+    };
+    let mut stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(&address)).await??;
+    stream.write_all(format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path, authority, body.len(), body,
+    ).as_bytes()).await?;
+    let mut response = Vec::new();
+    tokio::time::timeout(TIMEOUT, stream.read_to_end(&mut response)).await??;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or("");
+    if !status.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2')) {
+        return Err(Box::new(AlertError::Status(String::from(status))));
+    }
+    return Ok(());
+}
+
+impl Alerts {
+    pub fn new(config: Option<AlertsConfig>) -> Alerts {
+        return Alerts { config, sent_at: Mutex::new(BTreeMap::new()) };
+    }
+
+    pub fn low_battery_charge(&self) -> Option<f32> {
+        return self.config.as_ref().map(|config| config.low_battery_charge.unwrap_or(20.0));
+    }
+
+    // Sent in the background, a slow or unreachable webhook never holds up
+    // the daemon.
+    pub fn send(&self, alert: Alert, message: String) {
+        let config = match self.config {
+            Some(ref config) => config,
+            None => { return; },
+        };
+        if !config.events.as_ref().is_none_or(|events| events.contains(&alert)) {
+            return;
+        }
+        let min_interval = Duration::from_secs(config.min_interval.unwrap_or(300));
+        let now = Instant::now();
+        let mut sent_at = self.sent_at.lock().unwrap();
+        if sent_at.get(&alert).is_some_and(|sent_at| now.duration_since(*sent_at) < min_interval) {
+            log::debug!("Not sending the {} alert, one was sent less than {}s ago", alert.name(), min_interval.as_secs());
+            return;
+        }
+        sent_at.insert(alert, now);
+        let body = render(config.template.as_deref().unwrap_or(DEFAULT_TEMPLATE), alert, &message);
+        let url = config.url.clone();
+        tokio::task::spawn_local(async move {
+            if let Err(error) = post(&url, &body).await {
+                log::warn!("Failed to send the {} alert: {}", alert.name(), error);
+            }
+        });
+    }
+}
@@ -0,0 +1,173 @@
+#![allow(clippy::needless_return)]
+
+// The tray icon itself is drawn by yad, which takes its icon, tooltip and menu
+// as commands on its standard input, so that no toolkit is linked in. The
+// menu entries run argononectl.
+
+use clap::Parser;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::{ChildStdin, Command, Stdio};
+use std::time::Duration;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const WARNING_ICON: &str = "dialog-warning";
+
+#[derive(Parser)]
+#[command(version, about = "Show the temperature and fan speed of the argonone daemon in the system tray")]
+struct Cli {
+    /// Path to the control socket of the daemon
+    #[arg(short, long, default_value = "/run/argononed.sock")]
+    socket: String,
+    /// Icon name shown while no fan is above its critical temperature
+    #[arg(long, default_value = "utilities-system-monitor")]
+    icon: String,
+    /// Path to the argononectl binary run by the menu entries
+    #[arg(long, default_value = "argononectl")]
+    argononectl: String,
+    /// Path to the yad binary drawing the tray icon
+    #[arg(long, default_value = "yad")]
+    yad: String,
+}
+
+struct Fan {
+    temperature: Option<f64>,
+    speed: u64,
+    critical: bool,
+}
+
+struct Tray {
+    yad: ChildStdin,
+    icon: String,
+    unit: String,
+    fans: BTreeMap<String, Fan>,
+}
+
+// A single request, answered by the lines up to the empty one.
+fn request(socket: &str, request: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", request)?;
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+    if let Some(error) = status.strip_prefix("error: ") {
+        return Err(Box::from(String::from(error)));
+    }
+    let mut response = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        response.push(line);
+    }
+    return Ok(response);
+}
+
+impl Tray {
+    // yad exits along with its pipe, and the tray along with yad.
+    fn send(&mut self, command: &str) {
+        if writeln!(self.yad, "{}", command.replace('\n', " ")).is_err() {
+            std::process::exit(0);
+        }
+    }
+
+    fn set_menu(&mut self, cli: &Cli, profiles: &[String]) {
+        let argononectl = format!("{} --socket {}", cli.argononectl, cli.socket);
+        let mut entries = vec![format!("Follow the schedule!{} profile auto", argononectl)];
+        entries.extend(profiles.iter().map(|profile| format!("Profile {}!{} profile {}", profile, argononectl, profile)));
+        entries.push(format!("Full speed for 10 minutes!{} set-speed 100 --for 10m", argononectl));
+        entries.push(format!("Automatic speed!{} set-speed auto", argononectl));
+        entries.push(String::from("Quit!quit"));
+        self.send(&format!("menu:{}", entries.join("|")));
+    }
+
+    fn show(&mut self) {
+        let tooltip: Vec<String> = self.fans.iter().map(|(name, fan)| match fan.temperature {
+            Some(temperature) => format!("{}: {:.1}{} {}%", name, temperature, self.unit, fan.speed),
+            None => format!("{}: {}%", name, fan.speed),
+        }).collect();
+        let icon = match self.fans.values().any(|fan| fan.critical) {
+            true => String::from(WARNING_ICON),
+            false => self.icon.clone(),
+        };
+        self.send(&format!("icon:{}", icon));
+        self.send(&format!("tooltip:{}", tooltip.join(", ")));
+    }
+
+    fn status(&mut self, socket: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let status: serde_json::Value = serde_json::from_str(&request(socket, "status json")?.join("\n"))?;
+        self.unit = String::from(status["unit"].as_str().unwrap_or(""));
+        self.fans.clear();
+        for fan in status["fans"].as_array().into_iter().flatten() {
+            self.fans.insert(String::from(fan["name"].as_str().unwrap_or("")), Fan {
+                temperature: fan["temperature"].as_f64(),
+                speed: fan["fan_speed"].as_u64().unwrap_or(0),
+                critical: fan["critical"].as_bool().unwrap_or(false),
+            });
+        }
+        return Ok(());
+    }
+
+    fn event(&mut self, event: &serde_json::Value) {
+        let name = match event["fan"].as_str() {
+            Some(name) => String::from(name),
+            None => { return; },
+        };
+        let fan = self.fans.entry(name).or_insert(Fan { temperature: None, speed: 0, critical: false });
+        match event["event"].as_str() {
+            Some("temperature") => {
+                fan.temperature = event["temperature"].as_f64();
+                fan.speed = event["speed"].as_u64().unwrap_or(fan.speed);
+            },
+            Some("speed_change") => { fan.speed = event["speed"].as_u64().unwrap_or(fan.speed); },
+            Some("threshold") => { fan.critical = event["above"].as_bool().unwrap_or(false); },
+            _ => { return; },
+        };
+        self.show();
+    }
+
+    // Follows the events of the daemon until it goes away.
+    fn follow(&mut self, cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_menu(cli, &request(&cli.socket, "profiles")?);
+        self.status(&cli.socket)?;
+        self.show();
+        let mut stream = UnixStream::connect(&cli.socket)?;
+        writeln!(stream, "subscribe")?;
+        let mut lines = BufReader::new(stream).lines();
+        lines.next().transpose()?;
+        for line in lines {
+            self.event(&serde_json::from_str(&line?)?);
+        }
+        return Ok(());
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let mut child = Command::new(&cli.yad)
+        .args(["--notification", "--listen", "--no-middle"])
+        .arg(format!("--image={}", cli.icon))
+        .arg("--text=argononed")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Failed to run {}: {}", cli.yad, error))?;
+    let mut tray = Tray {
+        yad: child.stdin.take().unwrap(),
+        icon: cli.icon.clone(),
+        unit: String::new(),
+        fans: BTreeMap::new(),
+    };
+    std::thread::spawn(move || {
+        let _ = child.wait();
+        std::process::exit(0);
+    });
+    loop {
+        if let Err(error) = tray.follow(&cli) {
+            eprintln!("Error: {}", error);
+        }
+        tray.send(&format!("icon:{}", WARNING_ICON));
+        tray.send(&format!("tooltip:The daemon is not running on {}", cli.socket));
+        std::thread::sleep(RECONNECT_INTERVAL);
+    }
+}
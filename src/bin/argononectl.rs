@@ -0,0 +1,131 @@
+#![allow(clippy::needless_return)]
+
+#[path = "../generate.rs"]
+mod generate;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+#[derive(Parser)]
+#[command(name = "argononectl", version, about = "Control a running argonone daemon")]
+struct Cli {
+    /// Path to the control socket of the daemon
+    #[arg(short, long, default_value = "/run/argononed.sock")]
+    socket: String,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Show the active profile, or switch to another one ("auto" follows the schedule)
+    Profile { name: Option<String> },
+    /// List the configured profiles
+    Profiles,
+    /// Show the voltage and charge of the UPS battery
+    Battery,
+    /// Show the temperature, speed and profile of every fan
+    Status {
+        /// Print the status as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the forced fan speed, or force one ("auto" returns to automatic control)
+    SetSpeed {
+        speed: Option<String>,
+        /// Return to automatic control after this long, like 90s, 10m or 1h
+        #[arg(long = "for")]
+        duration: Option<String>,
+    },
+    /// Set how many seconds the fan controller keeps the power on after the OS shut down
+    SetPowerDelay { seconds: u8 },
+    /// Show whether the Pi powers on by itself when power is restored, or switch it "on" or "off"
+    AlwaysOn { switch: Option<String> },
+    /// Show the LED brightness set by hand, or set one (0 to 100, "on", "off", or "auto" for the configured behavior)
+    Led { brightness: Option<String> },
+    /// List the settings changed on the running daemon, or change one until it stops, like delay_on_change 15 or fan.drives.min_speed 30
+    Set {
+        key: Option<String>,
+        value: Vec<String>,
+        /// Write the setting to the configuration file as well
+        #[arg(long, requires = "key")]
+        save: bool,
+    },
+    /// Print the events of the daemon as they happen, one JSON object per line
+    Subscribe,
+    /// Print the completions of these arguments for a shell
+    Completions { shell: generate::Shell },
+    /// Print the man page of these arguments in roff
+    Manpage,
+}
+
+impl Commands {
+    fn request(&self) -> String {
+        return match self {
+            Commands::Profile { name: None } => String::from("profile"),
+            Commands::Profile { name: Some(name) } => format!("profile {}", name),
+            Commands::Profiles => String::from("profiles"),
+            Commands::Battery => String::from("battery"),
+            Commands::Status { json: false } => String::from("status"),
+            Commands::Status { json: true } => String::from("status json"),
+            Commands::SetSpeed { speed: None, .. } => String::from("set-speed"),
+            Commands::SetSpeed { speed: Some(speed), duration: None } => format!("set-speed {}", speed),
+            Commands::SetSpeed { speed: Some(speed), duration: Some(duration) } => format!("set-speed {} {}", speed, duration),
+            Commands::SetPowerDelay { seconds } => format!("set-power-delay {}", seconds),
+            Commands::AlwaysOn { switch: None } => String::from("always-on"),
+            Commands::AlwaysOn { switch: Some(switch) } => format!("always-on {}", switch),
+            Commands::Led { brightness: None } => String::from("led"),
+            Commands::Led { brightness: Some(brightness) } => format!("led {}", brightness),
+            Commands::Set { key: None, .. } => String::from("set"),
+            Commands::Set { key: Some(key), value, save } => {
+                let mut request = vec!["set"];
+                if *save {
+                    request.push("--save");
+                }
+                request.push(key);
+                request.extend(value.iter().map(String::as_str));
+                request.join(" ")
+            },
+            Commands::Subscribe => String::from("subscribe"),
+            Commands::Completions { .. } | Commands::Manpage => unreachable!(),
+        };
+    }
+}
+
+// Scripts asking for JSON get the error as JSON too.
+fn fail(command: &Commands, error: &str) -> ! {
+    if let Commands::Status { json: true } = command {
+        println!("{}", serde_json::json!({ "error": error }));
+    }
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    // Generated locally, without asking the daemon.
+    match cli.command {
+        Commands::Completions { shell } => { return generate::completions(Cli::command(), shell); },
+        Commands::Manpage => { return generate::manpage(Cli::command()); },
+        _ => {},
+    };
+    let mut stream = match UnixStream::connect(&cli.socket) {
+        Ok(stream) => stream,
+        Err(error) => fail(&cli.command, &format!("Failed to connect to {}: {}", cli.socket, error)),
+    };
+    writeln!(stream, "{}", cli.command.request())?;
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        println!("{}", line);
+    }
+    if let Some(error) = status.strip_prefix("error: ") {
+        fail(&cli.command, error);
+    }
+    return Ok(());
+}
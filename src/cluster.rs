@@ -0,0 +1,144 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+use crate::{alerts, read_cpu_temperature, SharedState, Units};
+
+// Every report is a single datagram "argonone1 <token> <temperature> <name>",
+// the temperature in degrees Celsius.
+const MAGIC: &str = "argonone1";
+const MAX_REPORT: usize = 512;
+
+#[derive(Deserialize, Clone)]
+pub struct ClusterConfig {
+    token: String,
+    listen: Option<String>,
+    peers: Option<Vec<String>>,
+    name: Option<String>,
+    interval: Option<u64>,
+    max_age: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ClusterError {
+    NoReports(u64),
+}
+
+impl std::error::Error for ClusterError {}
+
+impl std::fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClusterError::NoReports(max_age) => write!(f, "No peer reported a temperature in the last {}s", max_age),
+        }
+    }
+}
+
+// The last temperature reported by every peer, which count for max_age
+// seconds.
+pub struct Cluster {
+    reports: Mutex<BTreeMap<String, (f32, Instant)>>,
+    max_age: Duration,
+}
+
+impl Default for Cluster {
+    fn default() -> Cluster {
+        return Cluster::new(None);
+    }
+}
+
+impl Cluster {
+    pub fn new(config: Option<&ClusterConfig>) -> Cluster {
+        let max_age = config.and_then(|config| config.max_age).unwrap_or(30);
+        return Cluster { reports: Mutex::new(BTreeMap::new()), max_age: Duration::from_secs(max_age) };
+    }
+
+    fn report(&self, peer: &str, temperature: f32, units: Units) {
+        let mut reports = self.reports.lock().unwrap();
+        if reports.get(peer).is_none_or(|(_, reported_at)| reported_at.elapsed() > self.max_age) {
+            log::info!("Peer '{}' reports {}", peer, units.format(temperature));
+        }
+        reports.insert(String::from(peer), (temperature, Instant::now()));
+    }
+
+    pub fn max_temperature(&self) -> Result<f32, ClusterError> {
+        return self.reports.lock().unwrap().values()
+            .filter(|(_, reported_at)| reported_at.elapsed() <= self.max_age)
+            .map(|(temperature, _)| *temperature)
+            .reduce(f32::max)
+            .ok_or(ClusterError::NoReports(self.max_age.as_secs()));
+    }
+}
+
+fn parse<'a>(token: &str, report: &'a str) -> Option<(&'a str, f32)> {
+    let mut words = report.trim_end().splitn(4, ' ');
+    if words.next()? != MAGIC || words.next()? != token {
+        return None;
+    }
+    let temperature = words.next()?.parse::<f32>().ok().filter(|temperature| temperature.is_finite())?;
+    return Some((words.next()?, temperature));
+}
+
+// Listens for the reports of the peers if listen is set, and reports the CPU
+// temperature of this node to the peers every interval seconds if any are.
+pub async fn cluster_check(config: ClusterConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let name = config.name.clone().unwrap_or_else(alerts::hostname);
+    let interval = Duration::from_secs(config.interval.unwrap_or(5));
+    let peers = config.peers.clone().unwrap_or_default();
+    let socket = UdpSocket::bind(config.listen.as_deref().unwrap_or("0.0.0.0:0")).await?;
+    if let Some(ref listen) = config.listen {
+        log::info!("Listening for the temperatures of the cluster on {}", listen);
+    }
+    let mut next_report = tokio::time::Instant::now();
+    let mut buffer = [0u8; MAX_REPORT];
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buffer), if config.listen.is_some() => {
+                let (length, address) = received?;
+                match parse(&config.token, &String::from_utf8_lossy(&buffer[..length])) {
+                    Some((peer, temperature)) => state.cluster.report(peer, temperature, state.units),
+                    None => log::debug!("Ignoring an invalid cluster report from {}", address),
+                };
+            },
+            _ = tokio::time::sleep_until(next_report), if !peers.is_empty() => {
+                next_report += interval;
+                let temperature = match read_cpu_temperature().await {
+                    Ok(temperature) => temperature,
+                    Err(error) => {
+                        log::warn!("Failed to read the temperature to report to the cluster: {}", error);
+                        continue;
+                    },
+                };
+                let report = format!("{} {} {:.1} {}", MAGIC, config.token, temperature, name);
+                for peer in peers.iter() {
+                    if let Err(error) = socket.send_to(report.as_bytes(), peer.as_str()).await {
+                        log::warn!("Failed to report the temperature to {}: {}", peer, error);
+                    }
+                }
+            },
+            _ = state.termination.requested() => { return Ok(()); },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        assert_eq!(parse("secret", "argonone1 secret 55.5 pi2\n"), Some(("pi2", 55.5)));
+        assert_eq!(parse("secret", "argonone1 secret 55.5 rack 2"), Some(("rack 2", 55.5)));
+    }
+
+    #[test]
+    fn rejected_reports() {
+        assert_eq!(parse("secret", "argonone1 wrong 55.5 pi2"), None);
+        assert_eq!(parse("secret", "argonone2 secret 55.5 pi2"), None);
+        assert_eq!(parse("secret", "argonone1 secret NaN pi2"), None);
+        assert_eq!(parse("secret", "argonone1 secret inf pi2"), None);
+        assert_eq!(parse("secret", "argonone1 secret 55.5"), None);
+    }
+}
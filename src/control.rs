@@ -0,0 +1,323 @@
+use serde::Deserialize;
+use std::os::unix::fs::PermissionsExt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{settings, status, SharedState, SpeedOverride};
+
+pub const DEFAULT_SOCKET: &str = "/run/argononed.sock";
+
+#[derive(Deserialize, Clone, Default)]
+pub struct ControlConfig {
+    socket: Option<String>,
+}
+
+impl ControlConfig {
+    pub fn socket(&self) -> String {
+        return self.socket.clone().unwrap_or_else(|| String::from(DEFAULT_SOCKET));
+    }
+}
+
+#[derive(Debug)]
+pub enum ControlError {
+    UnknownCommand(String),
+    UnknownProfile(String),
+    MissingArgument(&'static str),
+    InvalidSpeed(String),
+    InvalidDuration(String),
+    InvalidDelay(String),
+    InvalidSwitch(String),
+    InvalidBrightness(String),
+    NoBattery,
+    Mcu(&'static str, String),
+    Status(String),
+    Setting(settings::SettingError),
+}
+
+impl std::error::Error for ControlError {}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ControlError::UnknownCommand(command) => write!(f, "Unknown command '{}'", command),
+            ControlError::UnknownProfile(profile) => write!(f, "Unknown profile '{}'", profile),
+            ControlError::MissingArgument(argument) => write!(f, "Missing argument {}", argument),
+            ControlError::InvalidSpeed(speed) => write!(f, "Invalid speed '{}', expected 0 to 100 or auto", speed),
+            ControlError::NoBattery => write!(f, "No battery reading, is a UPS configured?"),
+            ControlError::InvalidDelay(delay) => write!(f, "Invalid delay '{}', expected 0 to 255 seconds", delay),
+            ControlError::InvalidSwitch(switch) => write!(f, "Invalid value '{}', expected on or off", switch),
+            ControlError::InvalidBrightness(brightness) => write!(f, "Invalid brightness '{}', expected 0 to 100, on, off or auto", brightness),
+            ControlError::Mcu(operation, error) => write!(f, "Failed to {}: {}", operation, error),
+            ControlError::Status(error) => write!(f, "Failed to encode the status: {}", error),
+            ControlError::Setting(error) => write!(f, "{}", error),
+            ControlError::InvalidDuration(duration) => write!(f, "Invalid duration '{}', expected a number of seconds or minutes or hours like 90s, 10m or 1h", duration),
+        }
+    }
+}
+
+pub fn parse_duration(duration: &str) -> Result<Duration, ControlError> {
+    let invalid = || ControlError::InvalidDuration(String::from(duration));
+    let (value, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let value = value.parse::<u64>().map_err(|_| invalid())?;
+    return match unit {
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 60 * 60)),
+        _ => Err(invalid()),
+    };
+}
+
+pub struct Controller {
+    state: SharedState,
+    profiles: Vec<String>,
+}
+
+impl Controller {
+    pub fn new(state: SharedState, profiles: Vec<String>) -> Controller {
+        return Controller { state, profiles };
+    }
+
+    fn profile(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        let mut profile_override = self.state.profile_override.lock().unwrap();
+        match arguments.first() {
+            None => {},
+            Some(&"auto") => { *profile_override = None; },
+            Some(profile) => {
+                if !self.profiles.iter().any(|name| name == profile) {
+                    return Err(ControlError::UnknownProfile(String::from(*profile)));
+                }
+                *profile_override = Some(String::from(*profile));
+            },
+        };
+        // The fans switch to the curve of the profile right away.
+        if !arguments.is_empty() {
+            self.state.speed_override_changed.notify_waiters();
+        }
+        return Ok(match *profile_override {
+            Some(ref profile) => profile.clone(),
+            None => String::from("auto"),
+        });
+    }
+
+    fn set_speed(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        match arguments.first() {
+            None => {},
+            Some(&"auto") => { *self.state.speed_override.lock().unwrap() = None; },
+            Some(speed) => {
+                let speed = speed.parse::<u8>().ok()
+                    .filter(|speed| *speed <= 100)
+                    .ok_or_else(|| ControlError::InvalidSpeed(String::from(*speed)))?;
+                let until = match arguments.get(1) {
+                    Some(duration) => Some(Instant::now() + parse_duration(duration)?),
+                    None => None,
+                };
+                *self.state.speed_override.lock().unwrap() = Some(SpeedOverride { speed, until });
+            },
+        };
+        self.state.speed_override_changed.notify_waiters();
+        return Ok(match self.state.active_speed_override() {
+            Some(SpeedOverride { speed, until: Some(until) }) => format!("{}% for {}s", speed, until.saturating_duration_since(Instant::now()).as_secs()),
+            Some(SpeedOverride { speed, until: None }) => format!("{}%", speed),
+            None => String::from("auto"),
+        });
+    }
+
+    fn battery(&self) -> Result<String, ControlError> {
+        return match *self.state.battery.lock().unwrap() {
+            Some(battery) => Ok(format!("{:.2}V {:.0}%", battery.voltage, battery.charge)),
+            None => Err(ControlError::NoBattery),
+        };
+    }
+
+    fn set_power_delay(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        let delay = arguments.first().ok_or(ControlError::MissingArgument("seconds"))?;
+        let seconds = delay.parse::<u8>().map_err(|_| ControlError::InvalidDelay(String::from(*delay)))?;
+        let operation = "set the power cut delay";
+        let mcu = self.state.mcu.as_ref().ok_or_else(|| ControlError::Mcu(operation, String::from("no fan controller")))?;
+        mcu.set_power_cut_delay(seconds).map_err(|error| ControlError::Mcu(operation, error.to_string()))?;
+        return Ok(format!("{}s", seconds));
+    }
+
+    fn always_on(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        let operation = match arguments.first() {
+            None => "read the always on mode",
+            Some(_) => "set the always on mode",
+        };
+        let mcu = self.state.mcu.as_ref().ok_or_else(|| ControlError::Mcu(operation, String::from("no fan controller")))?;
+        let always_on = match arguments.first() {
+            None => mcu.always_on(),
+            Some(&"on") => mcu.set_always_on(true).map(|()| true),
+            Some(&"off") => mcu.set_always_on(false).map(|()| false),
+            Some(switch) => { return Err(ControlError::InvalidSwitch(String::from(*switch))); },
+        }.map_err(|error| ControlError::Mcu(operation, error.to_string()))?;
+        return Ok(String::from(if always_on { "on" } else { "off" }));
+    }
+
+    fn led(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        let mut led_override = self.state.led_override.lock().unwrap();
+        match arguments.first() {
+            None => {},
+            Some(&"auto") => { *led_override = None; },
+            Some(&"on") => { *led_override = Some(100); },
+            Some(&"off") => { *led_override = Some(0); },
+            Some(brightness) => {
+                let brightness = brightness.parse::<u8>().ok()
+                    .filter(|brightness| *brightness <= 100)
+                    .ok_or_else(|| ControlError::InvalidBrightness(String::from(*brightness)))?;
+                *led_override = Some(brightness);
+            },
+        };
+        self.state.led_changed.notify_waiters();
+        return Ok(match *led_override {
+            Some(brightness) => format!("{}%", brightness),
+            None => String::from("auto"),
+        });
+    }
+
+    fn status(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        return match arguments.first() {
+            None => Ok(status::status_text(&self.state)),
+            Some(&"json") => status::status_json(&self.state).map_err(|error| ControlError::Status(error.to_string())),
+            Some(format) => Err(ControlError::UnknownCommand(format!("status {}", format))),
+        };
+    }
+
+    // "set [--save] <key> <value>", the value being everything after the key.
+    // Without arguments, lists what was set.
+    fn set(&self, arguments: &[&str]) -> Result<String, ControlError> {
+        let (persist, arguments) = match arguments.split_first() {
+            Some((&"--save", arguments)) => (true, arguments),
+            _ => (false, arguments),
+        };
+        let key = match arguments.first() {
+            Some(key) => key,
+            None if !persist => { return Ok(self.state.settings.list()); },
+            None => { return Err(ControlError::MissingArgument("key")); },
+        };
+        if arguments.len() < 2 {
+            return Err(ControlError::MissingArgument("value"));
+        }
+        let setting = self.state.settings.set(key, &arguments[1..].join(" "), self.state.units, persist).map_err(ControlError::Setting)?;
+        // Cuts the waits short like a speed override, so that it applies
+        // right away.
+        self.state.speed_override_changed.notify_waiters();
+        return Ok(setting);
+    }
+
+    fn profiles(&self) -> Result<String, ControlError> {
+        return Ok(self.profiles.join("\n"));
+    }
+
+    pub fn handle(&self, request: &str) -> Result<String, ControlError> {
+        let mut words = request.split_whitespace();
+        let command = words.next().ok_or(ControlError::MissingArgument("command"))?;
+        let arguments: Vec<&str> = words.collect();
+        return match command {
+            "profile" => self.profile(&arguments),
+            "profiles" => self.profiles(),
+            "set-speed" => self.set_speed(&arguments),
+            "battery" => self.battery(),
+            "status" => self.status(&arguments),
+            "set-power-delay" => self.set_power_delay(&arguments),
+            "always-on" => self.always_on(&arguments),
+            "led" => self.led(&arguments),
+            "health" => Ok(self.state.health.report()),
+            "set" => self.set(&arguments),
+            _ => Err(ControlError::UnknownCommand(String::from(command))),
+        };
+    }
+
+    // Events are written until the client hangs up, anything else it sends
+    // being ignored, or until the daemon stops.
+    async fn subscribe(&self, mut requests: Lines<BufReader<OwnedReadHalf>>, mut writer: OwnedWriteHalf) -> Result<(), Box<dyn std::error::Error>> {
+        let mut events = self.state.events.subscribe();
+        writer.write_all(b"ok\n").await?;
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                request = requests.next_line() => match request? {
+                    Some(_) => { continue; },
+                    None => { break; },
+                },
+                _ = self.state.termination.requested() => { break; },
+            };
+            match event {
+                Ok(event) => writer.write_all(format!("{}\n", event).as_bytes()).await?,
+                Err(RecvError::Lagged(missed)) => log::warn!("A subscriber on the control socket missed {} events", missed),
+                Err(RecvError::Closed) => { break; },
+            };
+        }
+        return Ok(());
+    }
+
+    async fn serve(&self, stream: UnixStream) -> Result<(), Box<dyn std::error::Error>> {
+        let (reader, mut writer) = stream.into_split();
+        let mut requests = BufReader::new(reader).lines();
+        while let Some(request) = requests.next_line().await? {
+            if request.trim() == "subscribe" {
+                return self.subscribe(requests, writer).await;
+            }
+            let reply = match self.handle(&request) {
+                Ok(response) if response.is_empty() => String::from("ok\n\n"),
+                Ok(response) => format!("ok\n{}\n\n", response),
+                Err(error) => format!("error: {}\n\n", error),
+            };
+            writer.write_all(reply.as_bytes()).await?;
+        }
+        return Ok(());
+    }
+}
+
+// Every request is a single line, answered by "ok" or "error: <message>",
+// the lines of the response if any, and an empty line. After "subscribe" the
+// connection only carries events, one JSON object per line. The socket is
+// removed again once the daemon stops.
+pub async fn control_check(config: ControlConfig, profiles: Vec<String>, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = config.socket();
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)?;
+    std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o660))?;
+    log::info!("Listening for commands on {}", socket);
+    let controller = Rc::new(Controller::new(state, profiles));
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = controller.state.termination.requested() => { break; },
+        };
+        let controller = Rc::clone(&controller);
+        tokio::task::spawn_local(async move {
+            if let Err(error) = controller.serve(stream).await {
+                log::warn!("Error serving a control connection: {}", error);
+            }
+        });
+    }
+    std::fs::remove_file(&socket)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for duration in ["", "m", "10d", "1.5h", "-1s", "10 m"] {
+            assert!(matches!(parse_duration(duration), Err(ControlError::InvalidDuration(_))), "{}", duration);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Serialize;
+
+use crate::{apply_environment, apply_units, load_config, quantize_speed, resolve_fans, resolve_profiles, target_speed};
+use crate::{ConfigFormat, FanConfig, Units, DEFAULT_DELAY_ON_CHANGE};
+
+const MARGIN: i16 = 10;
+const BAR_WIDTH: u8 = 50;
+
+#[derive(Serialize)]
+struct CurvePoint {
+    temperature: i16,
+    speed: u8,
+    critical: bool,
+    shutdown: bool,
+}
+
+// Temperatures are in the configured units, which the unit field names.
+#[derive(Serialize)]
+struct FanCurve {
+    fan: String,
+    profile: Option<String>,
+    source: Option<String>,
+    unit: &'static str,
+    constant_speed: Option<u8>,
+    points: Vec<CurvePoint>,
+    delay_on_change: u64,
+    ramp_rate: Option<u8>,
+}
+
+// The range around the steps and thresholds, in the configured units, the
+// last step of a curve usually being open-ended.
+fn temperature_range(fan_config: &FanConfig, units: Units) -> (i16, i16) {
+    let temperatures: Vec<i16> = fan_config.step.iter().flatten()
+        .map(|pair| pair.temperature)
+        .filter(|temperature| *temperature != i16::MAX)
+        .chain(fan_config.critical_temperature)
+        .chain(fan_config.shutdown_temperature)
+        .map(|temperature| units.convert_celsius(temperature as f32).round() as i16)
+        .collect();
+    let low = temperatures.iter().min().copied().unwrap_or(40);
+    let high = temperatures.iter().max().copied().unwrap_or(70);
+    return (low.saturating_sub(MARGIN), high.saturating_add(MARGIN));
+}
+
+fn fan_curve(fan: &str, profile: Option<&str>, source: Option<String>, fan_config: &FanConfig, units: Units) -> FanCurve {
+    let monitored = fan_config.dynamic == Some(true) || fan_config.critical_temperature.is_some();
+    let (low, high) = temperature_range(fan_config, units);
+    let points = match monitored {
+        true => (low..=high).map(|temperature| {
+            let celsius = units.to_celsius(temperature as f32);
+            let critical = fan_config.critical_temperature.is_some_and(|critical_temperature| celsius >= critical_temperature as f32);
+            return CurvePoint {
+                temperature,
+                speed: quantize_speed(target_speed(fan_config, Some(celsius), critical), fan_config.speed_granularity.unwrap_or(1)),
+                critical,
+                shutdown: fan_config.shutdown_temperature.is_some_and(|shutdown_temperature| celsius >= shutdown_temperature as f32),
+            };
+        }).collect(),
+        false => Vec::new(),
+    };
+    return FanCurve {
+        fan: String::from(fan),
+        profile: profile.map(String::from),
+        source,
+        unit: units.symbol(),
+        constant_speed: (!monitored).then(|| quantize_speed(target_speed(fan_config, None, false), fan_config.speed_granularity.unwrap_or(1))),
+        points,
+        delay_on_change: fan_config.delay_on_change.unwrap_or(DEFAULT_DELAY_ON_CHANGE),
+        ramp_rate: fan_config.ramp_rate.filter(|ramp_rate| *ramp_rate > 0),
+    };
+}
+
+// Rows marked with a * change the speed.
+fn print_curve(curve: &FanCurve) {
+    match (&curve.profile, &curve.source) {
+        (Some(profile), _) => println!("Fan '{}', profile '{}':", curve.fan, profile),
+        (None, Some(source)) => println!("Fan '{}', source '{}':", curve.fan, source),
+        (None, None) => println!("Fan '{}':", curve.fan),
+    };
+    if let Some(speed) = curve.constant_speed {
+        println!("  constant {}%", speed);
+        return;
+    }
+    let mut last_speed = None;
+    for point in curve.points.iter() {
+        let mut notes = Vec::new();
+        if point.critical {
+            notes.push("critical");
+        }
+        if point.shutdown {
+            notes.push("shutdown");
+        }
+        let marker = if last_speed == Some(point.speed) { ' ' } else { '*' };
+        last_speed = Some(point.speed);
+        let row = format!("  {:>4}{} {:>3}% {} {:<width$} {}", point.temperature, curve.unit, point.speed, marker,
+            "#".repeat((point.speed as usize * BAR_WIDTH as usize).div_ceil(100)), notes.join(" "), width = BAR_WIDTH as usize);
+        println!("{}", row.trim_end());
+    }
+    println!("  Slowing down waits {}s for the temperature to settle.", curve.delay_on_change);
+    if let Some(ramp_rate) = curve.ramp_rate {
+        println!("  Speed changes ramp by {}% per second.", ramp_rate);
+    }
+}
+
+// Goes through the same resolution as the daemon, so what is printed is what
+// the daemon would run at.
+pub fn curve_command(config_path: &str, format: Option<ConfigFormat>, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = load_config(config_path, format)?;
+    apply_environment(&mut config)?;
+    apply_units(&mut config);
+    let units = config.units.unwrap_or_default();
+    let profiles = config.profile.take().unwrap_or_default();
+    let fans = resolve_fans(config);
+    let mut curves = Vec::new();
+    for (name, fan_config) in fans.iter() {
+        let profile_configs = resolve_profiles(name, fan_config, &profiles)?;
+        curves.push(fan_curve(name, None, None, fan_config, units));
+        // Sources with steps of their own, which apply in every profile.
+        for source in fan_config.sources.iter().flatten().filter(|source| source.step.is_some()) {
+            let source_config = FanConfig {
+                step: source.step.clone(),
+                delay_on_change: source.delay_on_change.or(fan_config.delay_on_change),
+                ..fan_config.clone()
+            };
+            curves.push(fan_curve(name, None, Some(source.describe()), &source_config, units));
+        }
+        for (profile_name, profile_config) in profile_configs.iter() {
+            curves.push(fan_curve(name, Some(profile_name), None, profile_config, units));
+        }
+    }
+    match json {
+        true => println!("{}", serde_json::to_string(&curves)?),
+        false => curves.iter().for_each(print_curve),
+    };
+    return Ok(());
+}
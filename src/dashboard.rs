@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::control::Controller;
+use crate::status::status_json;
+use crate::{alerts, SharedState};
+
+const PAGE: &str = include_str!("dashboard.html");
+const MAX_BODY: usize = 1024;
+
+// The page only switches profiles, everything else stays with the control
+// socket and its group permission.
+const COMMANDS: [&str; 2] = ["profile", "profiles"];
+
+#[derive(Deserialize, Clone, Default)]
+pub struct DashboardConfig {
+    listen: Option<String>,
+    token: Option<String>,
+}
+
+// Who may talk to the dashboard besides the page itself.
+struct Access {
+    port: u16,
+    hostname: String,
+    token: Option<String>,
+}
+
+impl Access {
+    // A Host header naming some other domain is a page of that domain that
+    // had its name resolved to this address, DNS rebinding. Only addresses,
+    // localhost and the name of this host are taken.
+    fn host(&self, host: &str) -> bool {
+        let (name, port) = match host.strip_prefix('[').and_then(|host| host.split_once(']')) {
+            Some((name, rest)) => (name, rest.strip_prefix(':')),
+            None => match host.rsplit_once(':') {
+                Some((name, port)) => (name, Some(port)),
+                None => (host, None),
+            },
+        };
+        if port.map_or(80, |port| port.parse::<u16>().unwrap_or(0)) != self.port {
+            return false;
+        }
+        return name.parse::<IpAddr>().is_ok()
+            || name.eq_ignore_ascii_case("localhost")
+            || name.eq_ignore_ascii_case(&self.hostname)
+            || name.eq_ignore_ascii_case(&format!("{}.local", self.hostname));
+    }
+
+    // Browsers send the Origin of a page posting to another one, which a
+    // plain text POST does without asking first.
+    fn command(&self, headers: &Headers) -> bool {
+        let same_origin = match (&headers.origin, &headers.host) {
+            (None, _) => true,
+            (Some(origin), Some(host)) => origin.strip_prefix("http://") == Some(host.as_str()),
+            (Some(_), None) => false,
+        };
+        let authorized = match self.token {
+            Some(ref token) => headers.authorization.as_deref().and_then(|value| value.strip_prefix("Bearer ")) == Some(token.as_str()),
+            None => true,
+        };
+        return same_origin && authorized;
+    }
+}
+
+#[derive(Default)]
+struct Headers {
+    content_length: usize,
+    host: Option<String>,
+    origin: Option<String>,
+    authorization: Option<String>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: String) -> Response {
+        return Response { status, content_type, body };
+    }
+}
+
+// The error counters in the Prometheus text format.
+fn metrics(state: &SharedState) -> String {
+    let mut metrics = String::new();
+    for (name, help, count) in state.stats.counters().iter() {
+        metrics.push_str(&format!("# HELP argononed_{name}_total {help}\n# TYPE argononed_{name}_total counter\nargononed_{name}_total {count}\n", name = name, help = help, count = count));
+    }
+    return metrics;
+}
+
+fn command(controller: &Controller, body: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
+    let request = std::str::from_utf8(body)?;
+    if !request.split_whitespace().next().is_some_and(|command| COMMANDS.contains(&command)) {
+        return Ok(Response::new("403 Forbidden", "text/plain", format!("Only {} are allowed here", COMMANDS.join(" and "))));
+    }
+    return Ok(match controller.handle(request) {
+        Ok(response) => Response::new("200 OK", "text/plain", response),
+        Err(error) => Response::new("400 Bad Request", "text/plain", error.to_string()),
+    });
+}
+
+// Just enough HTTP for the page itself: one request per connection, the body
+// of a POST is a control socket request and answered like one.
+async fn serve(mut stream: TcpStream, access: &Access, controller: &Controller, state: &SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut headers = Headers::default();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => { headers.content_length = value.parse::<usize>()?.min(MAX_BODY); },
+                "host" => { headers.host = Some(String::from(value)); },
+                "origin" => { headers.origin = Some(String::from(value)); },
+                "authorization" => { headers.authorization = Some(String::from(value)); },
+                _ => {},
+            };
+        }
+    }
+    let mut body = vec![0u8; headers.content_length];
+    reader.read_exact(&mut body).await?;
+    let mut words = request_line.split_whitespace();
+    let response = match (words.next(), words.next()) {
+        _ if !headers.host.as_deref().is_some_and(|host| access.host(host)) => Response::new("421 Misdirected Request", "text/plain", String::from("Unknown host")),
+        (Some("GET"), Some("/")) => Response::new("200 OK", "text/html; charset=utf-8", String::from(PAGE)),
+        (Some("GET"), Some("/status")) => Response::new("200 OK", "application/json", status_json(state)?),
+        (Some("GET"), Some("/metrics")) => Response::new("200 OK", "text/plain; version=0.0.4", metrics(state)),
+        (Some("GET"), Some("/healthz")) => match state.health.problems().is_empty() {
+            true => Response::new("200 OK", "text/plain", state.health.report()),
+            false => Response::new("503 Service Unavailable", "text/plain", state.health.report()),
+        },
+        (Some("POST"), Some("/command")) if !access.command(&headers) => Response::new("403 Forbidden", "text/plain", String::from("Forbidden")),
+        (Some("POST"), Some("/command")) => command(controller, &body)?,
+        _ => Response::new("404 Not Found", "text/plain", String::from("Not found")),
+    };
+    writer.write_all(format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, response.content_type, response.body.len(), response.body,
+    ).as_bytes()).await?;
+    return Ok(());
+}
+
+pub async fn dashboard_check(config: DashboardConfig, profiles: Vec<String>, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let listen = config.listen.unwrap_or_else(|| String::from("127.0.0.1:8080"));
+    let listener = TcpListener::bind(&listen).await?;
+    log::info!("Serving the dashboard on http://{}", listen);
+    let access = Rc::new(Access { port: listener.local_addr()?.port(), hostname: alerts::hostname(), token: config.token });
+    let controller = Rc::new(Controller::new(Arc::clone(&state), profiles));
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = state.termination.requested() => { break; },
+        };
+        let access = Rc::clone(&access);
+        let controller = Rc::clone(&controller);
+        let state = Arc::clone(&state);
+        tokio::task::spawn_local(async move {
+            if let Err(error) = serve(stream, &access, &controller, &state).await {
+                log::warn!("Error serving a dashboard connection: {}", error);
+            }
+        });
+    }
+    return Ok(());
+}
@@ -0,0 +1,277 @@
+use serde::Deserialize;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::hardware::I2c;
+use crate::{SharedState, read_cpu_temperature, read_firmware_temperature};
+
+const OLED_ADDR: u16 = 0x3c;
+const OLED_WIDTH: usize = 128;
+const CHAR_WIDTH: usize = 6;
+const LINE_LENGTH: usize = OLED_WIDTH / CHAR_WIDTH;
+
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+// 5x7 glyphs for the printable ASCII range, one byte per column with the
+// least significant bit at the top, which is exactly the SSD1306 page layout.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00], [0x08, 0x2a, 0x1c, 0x2a, 0x08], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31], [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x41, 0x22, 0x14, 0x08, 0x00], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x01, 0x01],
+    [0x3e, 0x41, 0x41, 0x51, 0x32], [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x04, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x7f, 0x20, 0x18, 0x20, 0x7f], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x00, 0x7f, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x41, 0x41, 0x7f, 0x00, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7e, 0x09, 0x01, 0x02], [0x08, 0x14, 0x54, 0x54, 0x3c],
+    [0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x00, 0x7f, 0x10, 0x28, 0x44], [0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+#[derive(Deserialize, Clone)]
+pub struct DisplayConfig {
+    address: Option<u16>,
+    height: Option<u8>,
+    refresh: Option<u64>,
+    pages: Option<Vec<Page>>,
+    disk_path: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Page {
+    Temperature,
+    Fan,
+    Ip,
+    Disk,
+}
+
+#[derive(Debug)]
+pub enum DisplayError {
+    UnsupportedHeight(u8),
+    EmptyPages,
+    ZeroRefresh,
+}
+
+impl std::error::Error for DisplayError {}
+
+impl std::fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DisplayError::UnsupportedHeight(height) => write!(f, "Unsupported display height {}, expected 32 or 64", height),
+            DisplayError::EmptyPages => write!(f, "Empty display page configuration"),
+            DisplayError::ZeroRefresh => write!(f, "The display refresh has to be at least 1s"),
+        }
+    }
+}
+
+impl DisplayConfig {
+    // Checked when the configuration loads, a page every 0s would rewrite the
+    // display as fast as the I2C bus allows, starving the fans of it.
+    pub fn validate(&self) -> Result<(), DisplayError> {
+        if let Some(height) = self.height.filter(|height| *height != 32 && *height != 64) {
+            return Err(DisplayError::UnsupportedHeight(height));
+        }
+        if self.pages.as_ref().is_some_and(|pages| pages.is_empty()) {
+            return Err(DisplayError::EmptyPages);
+        }
+        if self.refresh == Some(0) {
+            return Err(DisplayError::ZeroRefresh);
+        }
+        return Ok(());
+    }
+}
+
+struct Oled {
+    i2c_interface: Arc<Mutex<I2c>>,
+    address: u16,
+    height: u8,
+}
+
+impl Oled {
+    fn command(&self, commands: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut i2c_interface = self.i2c_interface.lock().unwrap();
+        i2c_interface.set_slave_address(self.address)?;
+        let mut buffer = vec![CONTROL_COMMAND];
+        buffer.extend_from_slice(commands);
+        i2c_interface.write(&buffer)?;
+        return Ok(());
+    }
+
+    fn init(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let com_pins = match self.height {
+            32 => 0x02,
+            _ => 0x12,
+        };
+        return self.command(&[
+            0xae,
+            0xd5, 0x80,
+            0xa8, self.height - 1,
+            0xd3, 0x00,
+            0x40,
+            0x8d, 0x14,
+            0x20, 0x02,
+            0xa1,
+            0xc8,
+            0xda, com_pins,
+            0x81, 0xcf,
+            0xd9, 0xf1,
+            0xdb, 0x40,
+            0xa4,
+            0xa6,
+            0xaf,
+        ]);
+    }
+
+    fn power_off(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return self.command(&[0xae]);
+    }
+
+    fn draw(&self, lines: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        for page in 0..(self.height / 8) {
+            let mut buffer = vec![CONTROL_DATA];
+            let line = lines.get(page as usize).map(|line| &line[..]).unwrap_or("");
+            for character in line.chars().chain(std::iter::repeat(' ')).take(LINE_LENGTH) {
+                let glyph = match character as usize {
+                    code @ 0x20..=0x7e => FONT[code - 0x20],
+                    _ => FONT[0],
+                };
+                buffer.extend_from_slice(&glyph);
+                buffer.push(0x00);
+            }
+            buffer.resize(OLED_WIDTH + 1, 0x00);
+            self.command(&[0xb0 + page, 0x00, 0x10])?;
+            let mut i2c_interface = self.i2c_interface.lock().unwrap();
+            i2c_interface.set_slave_address(self.address)?;
+            i2c_interface.write(&buffer)?;
+        }
+        return Ok(());
+    }
+}
+
+fn ip_address() -> Result<String, Box<dyn std::error::Error>> {
+    // Connecting a UDP socket sends nothing, it only picks the outgoing route.
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect("8.8.8.8:80")?;
+    return Ok(socket.local_addr()?.ip().to_string());
+}
+
+fn disk_usage(path: &str) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let path = std::ffi::CString::new(path)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(Box::new(std::io::Error::last_os_error()));
+    }
+    let total = stat.f_blocks as u64 * stat.f_frsize as u64;
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    return Ok((total - free, total));
+}
+
+async fn render(page: Page, disk_path: &str, state: &SharedState) -> Vec<String> {
+    let mut lines = Vec::new();
+    match page {
+        Page::Temperature => {
+            lines.push(String::from("Temperature"));
+            match read_cpu_temperature().await {
+                Ok(temperature) => lines.push(format!("CPU {}", state.units.format(temperature))),
+                Err(_) => lines.push(String::from("CPU unavailable")),
+            };
+            match read_firmware_temperature().await {
+                Ok(temperature) => lines.push(format!("Firmware {}", state.units.format(temperature))),
+                Err(_) => lines.push(String::from("Firmware unavailable")),
+            };
+            for (name, fan_state) in state.fans.lock().unwrap().iter() {
+                if let Some(temperature) = fan_state.temperature {
+                    lines.push(format!("{} {}", name, state.units.format(temperature)));
+                }
+            }
+        },
+        Page::Fan => {
+            lines.push(String::from("Fan speed"));
+            for (name, state) in state.fans.lock().unwrap().iter() {
+                lines.push(format!("{} {}%", name, state.fan_speed));
+            }
+        },
+        Page::Ip => {
+            lines.push(String::from("IP address"));
+            match ip_address() {
+                Ok(address) => lines.push(address),
+                Err(_) => lines.push(String::from("No network")),
+            };
+        },
+        Page::Disk => {
+            lines.push(format!("Disk {}", disk_path));
+            match disk_usage(disk_path) {
+                Ok((used, total)) => {
+                    let gigabyte = 1024.0 * 1024.0 * 1024.0;
+                    lines.push(format!("{:.1}/{:.1} GiB", used as f64 / gigabyte, total as f64 / gigabyte));
+                    if let Some(percent) = (used * 100).checked_div(total) {
+                        lines.push(format!("{}% used", percent));
+                    }
+                },
+                Err(_) => lines.push(String::from("Unavailable")),
+            };
+        },
+    };
+    return lines;
+}
+
+pub async fn display_check(config: DisplayConfig, i2c_interface: Arc<Mutex<I2c>>, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let height = config.height.unwrap_or(64);
+    let pages = config.pages.unwrap_or_else(|| vec![Page::Temperature, Page::Fan, Page::Ip, Page::Disk]);
+    let refresh = config.refresh.unwrap_or(5);
+    let disk_path = config.disk_path.unwrap_or_else(|| String::from("/"));
+    let oled = Oled {
+        i2c_interface,
+        address: config.address.unwrap_or(OLED_ADDR),
+        height,
+    };
+    oled.init()?;
+    let mut powered = true;
+    for page in pages.iter().cycle() {
+        match (state.display_off.load(Ordering::SeqCst), powered) {
+            (false, false) => { oled.init()?; },
+            (true, true) => { oled.power_off()?; },
+            _ => {},
+        };
+        powered = !state.display_off.load(Ordering::SeqCst);
+        if powered {
+            oled.draw(&render(*page, &disk_path, &state).await)?;
+        }
+        let terminating = tokio::select! {
+            terminating = state.termination.wait(Duration::from_secs(refresh)) => terminating,
+            _ = state.display_toggled.notified() => false,
+        };
+        if terminating {
+            if powered {
+                oled.power_off()?;
+            }
+            break;
+        }
+    }
+    return Ok(());
+}
@@ -0,0 +1,23 @@
+// Shell completions and a man page generated from the clap definitions by
+// clap_complete and clap_mangen, so that what packagers install always matches
+// the real arguments. Shared with argononectl, which includes this file as
+// well.
+
+use clap::Command;
+use std::io::Write;
+pub use clap_complete::Shell;
+
+// Rendered first, as clap_complete panics when it fails to write, such as into
+// a closed pipe.
+pub fn completions(mut command: Command, shell: Shell) -> Result<(), Box<dyn std::error::Error>> {
+    let name = String::from(command.get_name());
+    let mut completions = Vec::new();
+    clap_complete::generate(shell, &mut command, name, &mut completions);
+    std::io::stdout().write_all(&completions)?;
+    return Ok(());
+}
+
+pub fn manpage(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    clap_mangen::Man::new(command).render(&mut std::io::stdout())?;
+    return Ok(());
+}
@@ -0,0 +1,294 @@
+use serde::Deserialize;
+
+mod cdev;
+
+// Without the hardware feature the daemon runs against simulated devices, so
+// that it builds and runs on any Linux host.
+#[cfg(feature = "hardware")]
+use rppal::gpio as native_gpio;
+#[cfg(feature = "hardware")]
+use rppal::i2c as native_i2c;
+#[cfg(not(feature = "hardware"))]
+use stub as native_gpio;
+#[cfg(not(feature = "hardware"))]
+use stub as native_i2c;
+
+pub use native_gpio::{Level, Trigger};
+
+const DEFAULT_GPIO_CHIP: &str = "/dev/gpiochip0";
+const DEFAULT_I2C_BUS: u8 = 1;
+
+// "pi" goes through rppal, or the simulated devices without the hardware
+// feature. "cdev" goes through the GPIO character device and /dev/i2c-N, for
+// Argon compatible cases on other boards.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Pi,
+    Cdev,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct HardwareConfig {
+    pub backend: Option<Backend>,
+    pub gpio_chip: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum Gpio {
+    Native(native_gpio::Gpio),
+    Cdev(cdev::Gpio),
+}
+
+pub enum Pin {
+    Native(native_gpio::Pin),
+    Cdev(cdev::Pin),
+}
+
+pub enum InputPin {
+    Native(native_gpio::InputPin),
+    Cdev(cdev::InputPin),
+}
+
+impl Gpio {
+    pub fn open(config: &HardwareConfig) -> std::io::Result<Gpio> {
+        return match config.backend.unwrap_or_default() {
+            Backend::Pi => Ok(Gpio::Native(native_gpio::Gpio::new().map_err(std::io::Error::other)?)),
+            Backend::Cdev => Ok(Gpio::Cdev(cdev::Gpio::new(config.gpio_chip.as_deref().unwrap_or(DEFAULT_GPIO_CHIP))?)),
+        };
+    }
+
+    pub fn get(&self, pin: u8) -> std::io::Result<Pin> {
+        return match self {
+            Gpio::Native(gpio) => Ok(Pin::Native(gpio.get(pin).map_err(std::io::Error::other)?)),
+            Gpio::Cdev(gpio) => Ok(Pin::Cdev(gpio.get(pin)?)),
+        };
+    }
+}
+
+impl Pin {
+    pub fn into_input_pulldown(self) -> InputPin {
+        return match self {
+            Pin::Native(pin) => InputPin::Native(pin.into_input_pulldown()),
+            Pin::Cdev(pin) => InputPin::Cdev(pin.into_input_pulldown()),
+        };
+    }
+}
+
+impl InputPin {
+    pub fn set_async_interrupt<C>(&mut self, trigger: Trigger, callback: C) -> std::io::Result<()>
+    where
+        C: FnMut(Level) + Send + 'static,
+    {
+        return match self {
+            InputPin::Native(pin) => pin.set_async_interrupt(trigger, callback).map_err(std::io::Error::other),
+            InputPin::Cdev(pin) => pin.set_async_interrupt(callback),
+        };
+    }
+}
+
+pub enum I2c {
+    Native(native_i2c::I2c),
+    Cdev(cdev::I2c),
+}
+
+impl I2c {
+    // The bus of the Pi header unless given.
+    pub fn open(config: &HardwareConfig, bus: Option<u8>) -> std::io::Result<I2c> {
+        return match (config.backend.unwrap_or_default(), bus) {
+            (Backend::Pi, None) => Ok(I2c::Native(native_i2c::I2c::new().map_err(std::io::Error::other)?)),
+            (Backend::Pi, Some(bus)) => Ok(I2c::Native(native_i2c::I2c::with_bus(bus).map_err(std::io::Error::other)?)),
+            (Backend::Cdev, bus) => Ok(I2c::Cdev(cdev::I2c::with_bus(bus.unwrap_or(DEFAULT_I2C_BUS))?)),
+        };
+    }
+
+    pub fn set_slave_address(&mut self, address: u16) -> std::io::Result<()> {
+        return match self {
+            I2c::Native(i2c) => i2c.set_slave_address(address).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.set_slave_address(address),
+        };
+    }
+
+    pub fn smbus_quick_command(&self, command: bool) -> std::io::Result<()> {
+        return match self {
+            I2c::Native(i2c) => i2c.smbus_quick_command(command).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.smbus_quick_command(command),
+        };
+    }
+
+    pub fn smbus_read_byte(&self, command: u8) -> std::io::Result<u8> {
+        return match self {
+            I2c::Native(i2c) => i2c.smbus_read_byte(command).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.smbus_read_byte(command),
+        };
+    }
+
+    pub fn smbus_write_byte(&self, command: u8, value: u8) -> std::io::Result<()> {
+        return match self {
+            I2c::Native(i2c) => i2c.smbus_write_byte(command, value).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.smbus_write_byte(command, value),
+        };
+    }
+
+    pub fn smbus_read_word(&self, command: u8) -> std::io::Result<u16> {
+        return match self {
+            I2c::Native(i2c) => i2c.smbus_read_word(command).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.smbus_read_word(command),
+        };
+    }
+
+    pub fn smbus_read_word_swapped(&self, command: u8) -> std::io::Result<u16> {
+        return match self {
+            I2c::Native(i2c) => i2c.smbus_read_word_swapped(command).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.smbus_read_word_swapped(command),
+        };
+    }
+
+    pub fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        return match self {
+            I2c::Native(i2c) => i2c.write(buffer).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.write(buffer),
+        };
+    }
+
+    pub fn write_read(&self, write_buffer: &[u8], read_buffer: &mut [u8]) -> std::io::Result<()> {
+        return match self {
+            I2c::Native(i2c) => i2c.write_read(write_buffer, read_buffer).map_err(std::io::Error::other),
+            I2c::Cdev(i2c) => i2c.write_read(write_buffer, read_buffer),
+        };
+    }
+}
+
+#[cfg(not(feature = "hardware"))]
+mod stub {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Level {
+        Low,
+        High,
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    pub enum Trigger {
+        Both,
+    }
+
+    // The button is never pressed.
+    #[derive(Clone)]
+    pub struct Gpio;
+
+    pub struct Pin;
+
+    pub struct InputPin;
+
+    impl Gpio {
+        pub fn new() -> std::io::Result<Gpio> {
+            log::warn!("Built without the hardware feature, the GPIO is simulated");
+            return Ok(Gpio);
+        }
+
+        pub fn get(&self, _pin: u8) -> std::io::Result<Pin> {
+            return Ok(Pin);
+        }
+    }
+
+    impl Pin {
+        pub fn into_input_pulldown(self) -> InputPin {
+            return InputPin;
+        }
+    }
+
+    impl InputPin {
+        pub fn set_async_interrupt<C>(&mut self, _trigger: Trigger, _callback: C) -> std::io::Result<()>
+        where
+            C: FnMut(Level) + Send + 'static,
+        {
+            return Ok(());
+        }
+    }
+
+    // Every address answers like a device with 256 byte registers that read
+    // back what was last written to them, 0 until then. Writes are logged at
+    // debug level, and appended to the file named by ARGONONED_I2C_TRACE as
+    // the address and the bytes in hexadecimal, one write per line. Like the
+    // real bus, only changing the address takes a mutable reference.
+    pub struct I2c {
+        address: u16,
+        registers: RefCell<BTreeMap<(u16, u8), u8>>,
+        trace: Option<RefCell<File>>,
+    }
+
+    impl I2c {
+        pub fn new() -> std::io::Result<I2c> {
+            log::warn!("Built without the hardware feature, the I2C bus is simulated");
+            let trace = match std::env::var_os("ARGONONED_I2C_TRACE") {
+                Some(path) => Some(RefCell::new(std::fs::OpenOptions::new().create(true).append(true).open(path)?)),
+                None => None,
+            };
+            return Ok(I2c { address: 0, registers: RefCell::new(BTreeMap::new()), trace });
+        }
+
+        pub fn with_bus(_bus: u8) -> std::io::Result<I2c> {
+            return I2c::new();
+        }
+
+        pub fn set_slave_address(&mut self, address: u16) -> std::io::Result<()> {
+            self.address = address;
+            return Ok(());
+        }
+
+        fn read_register(&self, register: u8) -> u8 {
+            return self.registers.borrow().get(&(self.address, register)).copied().unwrap_or(0);
+        }
+
+        pub fn smbus_quick_command(&self, _command: bool) -> std::io::Result<()> {
+            return Ok(());
+        }
+
+        pub fn smbus_read_byte(&self, command: u8) -> std::io::Result<u8> {
+            return Ok(self.read_register(command));
+        }
+
+        pub fn smbus_write_byte(&self, command: u8, value: u8) -> std::io::Result<()> {
+            self.write(&[command, value])?;
+            return Ok(());
+        }
+
+        pub fn smbus_read_word(&self, command: u8) -> std::io::Result<u16> {
+            return Ok(u16::from_le_bytes([self.read_register(command), self.read_register(command.wrapping_add(1))]));
+        }
+
+        pub fn smbus_read_word_swapped(&self, command: u8) -> std::io::Result<u16> {
+            return Ok(self.smbus_read_word(command)?.swap_bytes());
+        }
+
+        // The first byte selects the register, the others are written to it
+        // and the following registers.
+        pub fn write(&self, buffer: &[u8]) -> std::io::Result<usize> {
+            log::debug!("I2C write to {:#x}: {:02x?}", self.address, buffer);
+            if let Some(ref trace) = self.trace {
+                let bytes: Vec<String> = buffer.iter().map(|byte| format!("{:02x}", byte)).collect();
+                writeln!(trace.borrow_mut(), "{:#04x} {}", self.address, bytes.join(" "))?;
+            }
+            if let Some((register, values)) = buffer.split_first() {
+                for (offset, value) in values.iter().enumerate() {
+                    self.registers.borrow_mut().insert((self.address, register.wrapping_add(offset as u8)), *value);
+                }
+            }
+            return Ok(buffer.len());
+        }
+
+        pub fn write_read(&self, write_buffer: &[u8], read_buffer: &mut [u8]) -> std::io::Result<()> {
+            let register = write_buffer.first().copied().unwrap_or(0);
+            for (offset, value) in read_buffer.iter_mut().enumerate() {
+                *value = self.read_register(register.wrapping_add(offset as u8));
+            }
+            return Ok(());
+        }
+    }
+}
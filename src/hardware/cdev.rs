@@ -0,0 +1,282 @@
+// The GPIO character device and i2c-dev interfaces of the kernel, which work
+// the same on any board, unlike rppal that needs to recognize a Raspberry Pi.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::Arc;
+
+use super::Level;
+
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+
+const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+const GPIO_V2_LINE_EVENT_SIZE: usize = 48;
+
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+const I2C_RDWR: libc::c_ulong = 0x0707;
+const I2C_SMBUS: libc::c_ulong = 0x0720;
+const I2C_M_RD: u16 = 0x0001;
+
+const I2C_SMBUS_WRITE: u8 = 0;
+const I2C_SMBUS_READ: u8 = 1;
+const I2C_SMBUS_QUICK: u32 = 0;
+const I2C_SMBUS_BYTE_DATA: u32 = 2;
+const I2C_SMBUS_WORD_DATA: u32 = 3;
+const I2C_SMBUS_BLOCK_MAX: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+// struct gpio_v2_line_request
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: libc::c_int,
+}
+
+// _IOWR(0xB4, 0x07, struct gpio_v2_line_request)
+const GPIO_V2_GET_LINE_IOCTL: libc::c_ulong = (3 << 30) | ((std::mem::size_of::<LineRequest>() as libc::c_ulong) << 16) | (0xb4 << 8) | 0x07;
+
+fn last_os_error<T>() -> std::io::Result<T> {
+    return Err(std::io::Error::last_os_error());
+}
+
+#[derive(Clone)]
+pub struct Gpio {
+    chip: String,
+}
+
+pub struct Pin {
+    chip: String,
+    offset: u32,
+}
+
+// The line is only requested from the kernel once the interrupt is set, and
+// released again along with the pin. The thread waiting for its edges also
+// waits for the stop eventfd, so that it sleeps until either happens.
+pub struct InputPin {
+    chip: String,
+    offset: u32,
+    stop: Option<Arc<File>>,
+}
+
+fn stop_event() -> std::io::Result<File> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return last_os_error();
+    }
+    return Ok(unsafe { File::from_raw_fd(fd) });
+}
+
+fn signal_stop(stop: &File) {
+    let _ = (&*stop).write_all(&1u64.to_ne_bytes());
+}
+
+impl Gpio {
+    pub fn new(chip: &str) -> std::io::Result<Gpio> {
+        File::open(chip)?;
+        return Ok(Gpio { chip: String::from(chip) });
+    }
+
+    // Pins are the line offsets on the chip.
+    pub fn get(&self, pin: u8) -> std::io::Result<Pin> {
+        return Ok(Pin { chip: self.chip.clone(), offset: pin as u32 });
+    }
+}
+
+impl Pin {
+    pub fn into_input_pulldown(self) -> InputPin {
+        return InputPin { chip: self.chip, offset: self.offset, stop: None };
+    }
+}
+
+impl InputPin {
+    // Always both edges, the only trigger the daemon uses.
+    pub fn set_async_interrupt<C>(&mut self, mut callback: C) -> std::io::Result<()>
+    where
+        C: FnMut(Level) + Send + 'static,
+    {
+        let chip = File::open(&self.chip)?;
+        let mut request: LineRequest = unsafe { std::mem::zeroed() };
+        request.offsets[0] = self.offset;
+        request.consumer[..9].copy_from_slice(b"argononed");
+        request.config.flags = GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING | GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN;
+        request.num_lines = 1;
+        if unsafe { libc::ioctl(chip.as_raw_fd(), GPIO_V2_GET_LINE_IOCTL as _, &mut request) } < 0 {
+            return last_os_error();
+        }
+        let mut line = unsafe { File::from_raw_fd(request.fd) };
+        let stop = Arc::new(stop_event()?);
+        if let Some(previous) = self.stop.replace(Arc::clone(&stop)) {
+            signal_stop(&previous);
+        }
+        std::thread::spawn(move || {
+            let mut event = [0u8; GPIO_V2_LINE_EVENT_SIZE];
+            loop {
+                let mut polls = [
+                    libc::pollfd { fd: line.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: stop.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                ];
+                if unsafe { libc::poll(polls.as_mut_ptr(), polls.len() as libc::nfds_t, -1) } < 0 {
+                    match std::io::Error::last_os_error().kind() {
+                        std::io::ErrorKind::Interrupted => { continue; },
+                        _ => { break; },
+                    };
+                }
+                if polls[1].revents != 0 || line.read_exact(&mut event).is_err() {
+                    break;
+                }
+                // struct gpio_v2_line_event { u64 timestamp_ns; u32 id; ... }
+                let id = u32::from_ne_bytes([event[8], event[9], event[10], event[11]]);
+                callback(if id == GPIO_V2_LINE_EVENT_RISING_EDGE { Level::High } else { Level::Low });
+            }
+        });
+        return Ok(());
+    }
+}
+
+impl Drop for InputPin {
+    fn drop(&mut self) {
+        if let Some(ref stop) = self.stop {
+            signal_stop(stop);
+        }
+    }
+}
+
+#[repr(C)]
+struct I2cMsg {
+    addr: u16,
+    flags: u16,
+    len: u16,
+    buf: *mut u8,
+}
+
+#[repr(C)]
+struct I2cRdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+// union i2c_smbus_data, big enough for a block and aligned for a word.
+#[repr(C, align(2))]
+struct SmbusData {
+    block: [u8; I2C_SMBUS_BLOCK_MAX + 2],
+}
+
+#[repr(C)]
+struct SmbusIoctlData {
+    read_write: u8,
+    command: u8,
+    size: u32,
+    data: *mut SmbusData,
+}
+
+pub struct I2c {
+    file: File,
+    address: u16,
+}
+
+impl I2c {
+    pub fn with_bus(bus: u8) -> std::io::Result<I2c> {
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(format!("/dev/i2c-{}", bus))?;
+        return Ok(I2c { file, address: 0 });
+    }
+
+    pub fn set_slave_address(&mut self, address: u16) -> std::io::Result<()> {
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SLAVE as _, address as libc::c_ulong) } < 0 {
+            return last_os_error();
+        }
+        self.address = address;
+        return Ok(());
+    }
+
+    fn smbus(&self, read_write: u8, command: u8, size: u32, data: Option<&mut SmbusData>) -> std::io::Result<()> {
+        let mut request = SmbusIoctlData {
+            read_write,
+            command,
+            size,
+            data: data.map_or(std::ptr::null_mut(), |data| data as *mut SmbusData),
+        };
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SMBUS as _, &mut request) } < 0 {
+            return last_os_error();
+        }
+        return Ok(());
+    }
+
+    pub fn smbus_quick_command(&self, command: bool) -> std::io::Result<()> {
+        return self.smbus(command as u8, 0, I2C_SMBUS_QUICK, None);
+    }
+
+    pub fn smbus_read_byte(&self, command: u8) -> std::io::Result<u8> {
+        let mut data = SmbusData { block: [0; I2C_SMBUS_BLOCK_MAX + 2] };
+        self.smbus(I2C_SMBUS_READ, command, I2C_SMBUS_BYTE_DATA, Some(&mut data))?;
+        return Ok(data.block[0]);
+    }
+
+    pub fn smbus_write_byte(&self, command: u8, value: u8) -> std::io::Result<()> {
+        let mut data = SmbusData { block: [0; I2C_SMBUS_BLOCK_MAX + 2] };
+        data.block[0] = value;
+        return self.smbus(I2C_SMBUS_WRITE, command, I2C_SMBUS_BYTE_DATA, Some(&mut data));
+    }
+
+    pub fn smbus_read_word(&self, command: u8) -> std::io::Result<u16> {
+        let mut data = SmbusData { block: [0; I2C_SMBUS_BLOCK_MAX + 2] };
+        self.smbus(I2C_SMBUS_READ, command, I2C_SMBUS_WORD_DATA, Some(&mut data))?;
+        return Ok(u16::from_ne_bytes([data.block[0], data.block[1]]));
+    }
+
+    pub fn smbus_read_word_swapped(&self, command: u8) -> std::io::Result<u16> {
+        return Ok(self.smbus_read_word(command)?.swap_bytes());
+    }
+
+    pub fn write(&self, buffer: &[u8]) -> std::io::Result<usize> {
+        return (&self.file).write(buffer);
+    }
+
+    // A single transfer with a repeated start, so that no other master gets
+    // in between the register and the read.
+    pub fn write_read(&self, write_buffer: &[u8], read_buffer: &mut [u8]) -> std::io::Result<()> {
+        let mut write_buffer = write_buffer.to_vec();
+        let mut messages = [
+            I2cMsg { addr: self.address, flags: 0, len: write_buffer.len() as u16, buf: write_buffer.as_mut_ptr() },
+            I2cMsg { addr: self.address, flags: I2C_M_RD, len: read_buffer.len() as u16, buf: read_buffer.as_mut_ptr() },
+        ];
+        let mut request = I2cRdwrIoctlData { msgs: messages.as_mut_ptr(), nmsgs: messages.len() as u32 };
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_RDWR as _, &mut request) } < 0 {
+            return last_os_error();
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// On top of the longest a cycle may wait, for I2C retries, kick starts and a
+// slow sensor.
+pub const GRACE: Duration = Duration::from_secs(60);
+
+// Ordered from the most to the least serious, the exit code being the one of
+// the most serious problem. The codes start above 1, which the command exits
+// with when it fails itself, and 2, which clap exits with for invalid
+// arguments.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Class {
+    Unreachable,
+    Stalled,
+    StaleTemperature,
+    I2cWrite,
+}
+
+impl Class {
+    const ALL: [Class; 4] = [Class::Unreachable, Class::Stalled, Class::StaleTemperature, Class::I2cWrite];
+
+    fn name(self) -> &'static str {
+        return match self {
+            Class::Unreachable => "unreachable",
+            Class::Stalled => "stalled",
+            Class::StaleTemperature => "stale_temperature",
+            Class::I2cWrite => "i2c_write",
+        };
+    }
+
+    fn exit_code(self) -> i32 {
+        return match self {
+            Class::Unreachable => 3,
+            Class::Stalled => 4,
+            Class::StaleTemperature => 5,
+            Class::I2cWrite => 6,
+        };
+    }
+}
+
+pub struct Problem {
+    class: Class,
+    message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "{}: {}", self.class.name(), self.message);
+    }
+}
+
+struct FanHealth {
+    cycled_at: Instant,
+    due_within: Duration,
+    monitored: bool,
+    read_at: Instant,
+    write_failed: bool,
+}
+
+// What every fan task last did, kept up to date by the tasks themselves.
+#[derive(Default)]
+pub struct Health {
+    fans: Mutex<BTreeMap<String, FanHealth>>,
+}
+
+impl Health {
+    // Called as every cycle starts, with the longest it may take before the
+    // next one.
+    pub fn cycle(&self, fan: &str, due_within: Duration, monitored: bool) {
+        let now = Instant::now();
+        let mut fans = self.fans.lock().unwrap();
+        if !fans.contains_key(fan) {
+            fans.insert(String::from(fan), FanHealth { cycled_at: now, due_within, monitored, read_at: now, write_failed: false });
+        }
+        let fan_health = fans.get_mut(fan).unwrap();
+        fan_health.cycled_at = now;
+        fan_health.due_within = due_within;
+        fan_health.monitored = monitored;
+    }
+
+    pub fn read(&self, fan: &str) {
+        if let Some(fan_health) = self.fans.lock().unwrap().get_mut(fan) {
+            fan_health.read_at = Instant::now();
+        }
+    }
+
+    pub fn wrote(&self, fan: &str, succeeded: bool) {
+        if let Some(fan_health) = self.fans.lock().unwrap().get_mut(fan) {
+            fan_health.write_failed = !succeeded;
+        }
+    }
+
+    pub fn problems(&self) -> Vec<Problem> {
+        let fans = self.fans.lock().unwrap();
+        if fans.is_empty() {
+            return vec![Problem { class: Class::Stalled, message: String::from("No fan is controlled yet") }];
+        }
+        let mut problems = Vec::new();
+        for (name, fan_health) in fans.iter() {
+            let since_cycle = fan_health.cycled_at.elapsed();
+            if since_cycle > fan_health.due_within {
+                problems.push(Problem { class: Class::Stalled, message: format!("Fan '{}' has not been updated for {}s", name, since_cycle.as_secs()) });
+            }
+            let since_reading = fan_health.read_at.elapsed();
+            if fan_health.monitored && since_reading > fan_health.due_within {
+                problems.push(Problem { class: Class::StaleTemperature, message: format!("Fan '{}' has no temperature reading for {}s", name, since_reading.as_secs()) });
+            }
+            if fan_health.write_failed {
+                problems.push(Problem { class: Class::I2cWrite, message: format!("The last speed written for fan '{}' failed", name) });
+            }
+        }
+        problems.sort_by_key(|problem| problem.class);
+        return problems;
+    }
+
+    // One problem per line, or "healthy".
+    pub fn report(&self) -> String {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return String::from("healthy");
+        }
+        return problems.iter().map(Problem::to_string).collect::<Vec<String>>().join("\n");
+    }
+}
+
+// Asks the daemon on its control socket, and exits with the code of the most
+// serious problem.
+pub fn health_command(socket: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let unreachable = |error: &dyn std::fmt::Display| {
+        println!("{}", Problem { class: Class::Unreachable, message: format!("Failed to ask the daemon on {}: {}", socket, error) });
+        std::process::exit(Class::Unreachable.exit_code());
+    };
+    let mut stream = UnixStream::connect(socket).unwrap_or_else(|error| unreachable(&error));
+    writeln!(stream, "health")?;
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+    if status != "ok" {
+        unreachable(&status);
+    }
+    let mut exit_code = 0;
+    for line in lines {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        println!("{}", line);
+        let name = line.split(':').next().unwrap_or("");
+        if let Some(class) = Class::ALL.iter().find(|class| class.name() == name) {
+            if exit_code == 0 {
+                exit_code = class.exit_code();
+            }
+        }
+    }
+    std::process::exit(exit_code);
+}
@@ -0,0 +1,87 @@
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::SharedState;
+
+#[derive(Deserialize, Clone)]
+pub struct HistoryConfig {
+    path: Option<String>,
+    interval: Option<u64>,
+    rotate: Option<u64>,
+    keep: Option<u32>,
+}
+
+fn unix_time() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+}
+
+// The timestamp of the first sample tells how long the file has been in use,
+// also across restarts of the daemon.
+fn first_timestamp(path: &str) -> Option<u64> {
+    let history = std::fs::read_to_string(path).ok()?;
+    let sample = history.lines().nth(1)?;
+    return sample.split(',').next()?.parse::<u64>().ok();
+}
+
+fn rotated_path(path: &str, index: u32) -> String {
+    return format!("{}.{}", path, index);
+}
+
+fn ignore_not_found(result: std::io::Result<()>) -> std::io::Result<()> {
+    return match result {
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    };
+}
+
+// history.csv becomes history.csv.1, history.csv.1 becomes history.csv.2 and
+// so on, the oldest file beyond keep is removed.
+fn rotate_files(path: &str, keep: u32) -> std::io::Result<()> {
+    if keep == 0 {
+        return ignore_not_found(std::fs::remove_file(path));
+    }
+    ignore_not_found(std::fs::remove_file(rotated_path(path, keep)))?;
+    for index in (1..keep).rev() {
+        ignore_not_found(std::fs::rename(rotated_path(path, index), rotated_path(path, index + 1)))?;
+    }
+    return ignore_not_found(std::fs::rename(path, rotated_path(path, 1)));
+}
+
+fn format_samples(timestamp: u64, state: &SharedState) -> String {
+    let mut samples = String::new();
+    for (name, fan_state) in state.fans.lock().unwrap().iter() {
+        let temperature = match fan_state.temperature {
+            Some(temperature) => format!("{:.1}", state.units.convert_celsius(temperature)),
+            None => String::new(),
+        };
+        samples.push_str(&format!("{},{},{},{},{}\n", timestamp, name, temperature, fan_state.target_speed, fan_state.fan_speed));
+    }
+    return samples;
+}
+
+pub async fn history_check(config: HistoryConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config.path.unwrap_or_else(|| String::from("/var/log/argononed-history.csv"));
+    let interval = config.interval.unwrap_or(60);
+    let rotate = config.rotate.unwrap_or(24) * 60 * 60;
+    let keep = config.keep.unwrap_or(7);
+    let mut started = first_timestamp(&path);
+    log::info!("Recording the fan history to {}", path);
+    loop {
+        if state.termination.wait(Duration::from_secs(interval)).await {
+            return Ok(());
+        }
+        let now = unix_time();
+        if started.is_some_and(|started| now.saturating_sub(started) >= rotate) {
+            rotate_files(&path, keep)?;
+            started = None;
+        }
+        let mut history = OpenOptions::new().create(true).append(true).open(&path)?;
+        if started.is_none() {
+            writeln!(history, "timestamp,fan,temperature,target_speed,fan_speed")?;
+            started = Some(now);
+        }
+        history.write_all(format_samples(now, &state).as_bytes())?;
+    }
+}
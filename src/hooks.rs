@@ -0,0 +1,148 @@
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast;
+
+use crate::system::SystemActions;
+
+// Events a subscriber has not read yet, beyond which it misses the oldest.
+const EVENT_BUFFER: usize = 64;
+
+#[derive(Deserialize, Clone, Default)]
+pub struct HooksConfig {
+    on_speed_change: Option<String>,
+    on_threshold: Option<String>,
+    on_button: Option<String>,
+    on_stall: Option<String>,
+    on_shutdown: Option<String>,
+}
+
+pub enum Event<'a> {
+    SpeedChange { fan: &'a str, old_speed: u8, speed: u8 },
+    Threshold { fan: &'a str, threshold: &'a str, temperature: f32, above: bool },
+    Button { width: u64 },
+    Gesture { gesture: &'a str },
+    Stall { fan: &'a str, speed: u8, stalled: bool },
+    Shutdown { action: &'a str, reason: &'a str },
+    Temperature { fan: &'a str, temperature: Option<f32>, target_speed: u8, speed: u8 },
+    Profile { fan: &'a str, profile: Option<&'a str> },
+}
+
+impl<'a> Event<'a> {
+    fn name(&self) -> &'static str {
+        return match self {
+            Event::SpeedChange { .. } => "speed_change",
+            Event::Threshold { .. } => "threshold",
+            Event::Button { .. } => "button",
+            Event::Gesture { .. } => "gesture",
+            Event::Stall { .. } => "stall",
+            Event::Shutdown { .. } => "shutdown",
+            Event::Temperature { .. } => "temperature",
+            Event::Profile { .. } => "profile",
+        };
+    }
+
+    fn environment(&self) -> Vec<(&'static str, String)> {
+        let mut environment = vec![("ARGONONE_EVENT", String::from(self.name()))];
+        match self {
+            Event::SpeedChange { fan, old_speed, speed } => {
+                environment.push(("ARGONONE_FAN", String::from(*fan)));
+                environment.push(("ARGONONE_OLD_SPEED", old_speed.to_string()));
+                environment.push(("ARGONONE_SPEED", speed.to_string()));
+            },
+            Event::Threshold { fan, threshold, temperature, above } => {
+                environment.push(("ARGONONE_FAN", String::from(*fan)));
+                environment.push(("ARGONONE_THRESHOLD", String::from(*threshold)));
+                environment.push(("ARGONONE_TEMPERATURE", format!("{:.1}", temperature)));
+                environment.push(("ARGONONE_DIRECTION", String::from(if *above { "above" } else { "below" })));
+            },
+            Event::Button { width } => {
+                environment.push(("ARGONONE_PULSE_WIDTH", width.to_string()));
+            },
+            Event::Gesture { gesture } => {
+                environment.push(("ARGONONE_GESTURE", String::from(*gesture)));
+            },
+            Event::Stall { fan, speed, stalled } => {
+                environment.push(("ARGONONE_FAN", String::from(*fan)));
+                environment.push(("ARGONONE_SPEED", speed.to_string()));
+                environment.push(("ARGONONE_STALLED", String::from(if *stalled { "yes" } else { "no" })));
+            },
+            Event::Shutdown { action, reason } => {
+                environment.push(("ARGONONE_ACTION", String::from(*action)));
+                environment.push(("ARGONONE_REASON", String::from(*reason)));
+            },
+            Event::Temperature { fan, temperature, target_speed, speed } => {
+                environment.push(("ARGONONE_FAN", String::from(*fan)));
+                if let Some(temperature) = temperature {
+                    environment.push(("ARGONONE_TEMPERATURE", format!("{:.1}", temperature)));
+                }
+                environment.push(("ARGONONE_TARGET_SPEED", target_speed.to_string()));
+                environment.push(("ARGONONE_SPEED", speed.to_string()));
+            },
+            Event::Profile { fan, profile } => {
+                environment.push(("ARGONONE_FAN", String::from(*fan)));
+                environment.push(("ARGONONE_PROFILE", String::from(profile.unwrap_or("default"))));
+            },
+        };
+        return environment;
+    }
+
+    // The same fields as the environment of the hooks, the event name
+    // included.
+    fn json(&self) -> String {
+        let mut value = match self {
+            Event::SpeedChange { fan, old_speed, speed } => json!({ "fan": fan, "old_speed": old_speed, "speed": speed }),
+            Event::Threshold { fan, threshold, temperature, above } => json!({ "fan": fan, "threshold": threshold, "temperature": temperature, "above": above }),
+            Event::Button { width } => json!({ "pulse_width": width }),
+            Event::Gesture { gesture } => json!({ "gesture": gesture }),
+            Event::Stall { fan, speed, stalled } => json!({ "fan": fan, "speed": speed, "stalled": stalled }),
+            Event::Shutdown { action, reason } => json!({ "action": action, "reason": reason }),
+            Event::Temperature { fan, temperature, target_speed, speed } => json!({ "fan": fan, "temperature": temperature, "target_speed": target_speed, "speed": speed }),
+            Event::Profile { fan, profile } => json!({ "fan": fan, "profile": profile }),
+        };
+        value["event"] = json!(self.name());
+        return value.to_string();
+    }
+}
+
+impl HooksConfig {
+    // Hooks run in the background, a slow or failing script never holds up
+    // the daemon.
+    pub fn run(&self, event: Event, actions: &dyn SystemActions) {
+        let hook = match event {
+            Event::SpeedChange { .. } => &self.on_speed_change,
+            Event::Threshold { .. } => &self.on_threshold,
+            Event::Button { .. } | Event::Gesture { .. } => &self.on_button,
+            Event::Stall { .. } => &self.on_stall,
+            Event::Shutdown { .. } => &self.on_shutdown,
+            Event::Temperature { .. } | Event::Profile { .. } => &None,
+        };
+        if let Some(path) = hook {
+            if let Err(error) = actions.run_hook(path, event.environment()) {
+                log::warn!("Failed to run the {} hook {}: {}", event.name(), path, error);
+            }
+        }
+    }
+}
+
+// Every event as a line of JSON, for the subscribers on the control socket.
+pub struct EventStream {
+    sender: broadcast::Sender<String>,
+}
+
+impl Default for EventStream {
+    fn default() -> EventStream {
+        return EventStream { sender: broadcast::channel(EVENT_BUFFER).0 };
+    }
+}
+
+impl EventStream {
+    pub fn publish(&self, event: &Event) {
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(event.json());
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        return self.sender.subscribe();
+    }
+}
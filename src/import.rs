@@ -0,0 +1,88 @@
+// The configuration of the Python daemon shipped by Argon is a list of
+// temperature=speed lines, where a speed applies from its temperature on.
+// Steps here apply below their temperature, so every speed moves to the step
+// of the next temperature, and the first temperature gets a step at 0%.
+
+#[derive(Debug)]
+pub enum ImportError {
+    InvalidLine(usize, String),
+    NoSteps(String),
+}
+
+impl std::error::Error for ImportError {}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportError::InvalidLine(number, line) => write!(f, "Invalid line {} '{}', expected temperature=speed", number, line),
+            ImportError::NoSteps(path) => write!(f, "No temperature=speed lines in {}", path),
+        }
+    }
+}
+
+fn parse_pair(line: &str) -> Option<(i16, u8)> {
+    let (temperature, speed) = line.split_once('=')?;
+    let temperature = temperature.trim().parse::<f32>().ok()?.round() as i16;
+    let speed = speed.trim().parse::<f32>().ok()?.round().clamp(0.0, 100.0) as u8;
+    return Some((temperature, speed));
+}
+
+fn parse(path: &str, contents: &str) -> Result<Vec<(i16, u8)>, ImportError> {
+    let mut pairs = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        pairs.push(parse_pair(line).ok_or_else(|| ImportError::InvalidLine(index + 1, String::from(line)))?);
+    }
+    if pairs.is_empty() {
+        return Err(ImportError::NoSteps(String::from(path)));
+    }
+    pairs.sort_by_key(|(temperature, _)| *temperature);
+    return Ok(pairs);
+}
+
+fn to_toml(path: &str, pairs: &[(i16, u8)]) -> String {
+    let mut steps = Vec::new();
+    let mut speed = 0;
+    for (temperature, next_speed) in pairs.iter() {
+        steps.push(format!("  {{ temperature = {}, fan_speed = {} }},", temperature, speed));
+        speed = *next_speed;
+    }
+    steps.push(format!("  {{ temperature = {}, fan_speed = {} }},", i16::MAX, speed));
+    return format!("# Imported from {}\ndynamic = true\nstep = [\n{}\n]\n", path, steps.join("\n"));
+}
+
+pub fn import_command(input: &str, output: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = to_toml(input, &parse(input, &std::fs::read_to_string(input)?)?);
+    match output {
+        Some(output) => {
+            std::fs::write(output, config)?;
+            log::info!("Wrote the configuration imported from {} to {}", input, output);
+        },
+        None => print!("{}", config),
+    };
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_move_to_the_next_temperature() {
+        let pairs = parse("fanconfig", "# Start below:\n60=55\n55=10\n\n65 = 100.4\n").unwrap();
+        assert_eq!(pairs, [(55, 10), (60, 55), (65, 100)]);
+        assert_eq!(
+            to_toml("fanconfig", &pairs),
+            "# Imported from fanconfig\ndynamic = true\nstep = [\n  { temperature = 55, fan_speed = 0 },\n  { temperature = 60, fan_speed = 10 },\n  { temperature = 65, fan_speed = 55 },\n  { temperature = 32767, fan_speed = 100 },\n]\n",
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(matches!(parse("fanconfig", "55=10\nfast\n"), Err(ImportError::InvalidLine(2, line)) if line == "fast"));
+        assert!(matches!(parse("fanconfig", "# Nothing\n"), Err(ImportError::NoSteps(_))));
+    }
+}
@@ -0,0 +1,101 @@
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+use crate::SharedState;
+
+const WHO: &str = "argononed";
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Clone, Default)]
+pub struct InhibitConfig {
+    max_wait: Option<u64>,
+}
+
+// What ListInhibitors answers for every inhibitor: what, who, why, mode, uid
+// and pid.
+#[derive(Deserialize)]
+struct Inhibitor(String, String, String, String, IgnoredAny, u32);
+
+#[derive(Deserialize)]
+struct Inhibitors {
+    data: (Vec<Inhibitor>,),
+}
+
+// The shutdown inhibitors in block mode of other programs, such as a backup
+// in progress, as "who (why, pid)".
+async fn blocking_inhibitors() -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let output = Command::new("busctl")
+        .args(["--json=short", "call", "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "ListInhibitors"])
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(Box::from(format!("busctl failed with {}", output.status)));
+    }
+    let inhibitors: Inhibitors = serde_json::from_slice(&output.stdout)?;
+    return Ok(inhibitors.data.0.iter()
+        .filter(|Inhibitor(what, who, _, mode, _, _)| what.split(':').any(|what| what == "shutdown") && mode == "block" && who != WHO)
+        .map(|Inhibitor(_, who, why, _, _, pid)| format!("{} ({}, pid {})", who, why, pid))
+        .collect());
+}
+
+// A delay lock makes logind hold the shutdown back until the daemon exits, so
+// that the shutdown hook and the exit fan speed get their turn. systemd-inhibit
+// holds it for as long as cat runs, which is until its standard input, the
+// daemon, goes away.
+fn take_delay_lock(action: &str, state: &SharedState) {
+    let lock = std::process::Command::new("systemd-inhibit")
+        .args(["--what=shutdown", "--mode=delay", &format!("--who={}", WHO)])
+        .arg(format!("--why=Stopping the fan before the {}", action))
+        .arg("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match lock {
+        Ok(lock) => { *state.shutdown_lock.lock().unwrap() = Some(lock); },
+        Err(error) => log::warn!("Failed to take a delay lock from logind: {}", error),
+    };
+}
+
+// Waits up to max_wait seconds for the blocking inhibitors to be released.
+// Logind not answering is not a reason to keep the Pi running. Returns false
+// when the daemon is stopped during the wait, the inhibitors still holding
+// the shutdown back.
+pub async fn wait_for_inhibitors(config: &InhibitConfig, action: &str, state: &SharedState) -> bool {
+    take_delay_lock(action, state);
+    let max_wait = Duration::from_secs(config.max_wait.unwrap_or(300));
+    let started = Instant::now();
+    let mut waiting_for = BTreeSet::new();
+    while started.elapsed() < max_wait {
+        let inhibitors = match blocking_inhibitors().await {
+            Ok(inhibitors) => inhibitors,
+            Err(error) => {
+                log::warn!("Failed to list the inhibitors of logind: {}", error);
+                return true;
+            },
+        };
+        if inhibitors.is_empty() {
+            if !waiting_for.is_empty() {
+                log::info!("No inhibitor left, going ahead with the {}", action);
+            }
+            return true;
+        }
+        for inhibitor in inhibitors.difference(&waiting_for) {
+            log::info!("Waiting for {} before the {}", inhibitor, action);
+        }
+        waiting_for = inhibitors;
+        if state.termination.wait(POLL_INTERVAL.min(max_wait.saturating_sub(started.elapsed()))).await {
+            log::info!("Stopping while waiting for the inhibitors, not going ahead with the {}", action);
+            return false;
+        }
+    }
+    if !waiting_for.is_empty() {
+        log::warn!("Still inhibited after {}s, going ahead with the {}", max_wait.as_secs(), action);
+    }
+    return true;
+}
@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+
+use crate::action::Action;
+use crate::SharedState;
+
+const LIRC_SET_REC_MODE: libc::c_ulong = 0x4004_6912;
+const LIRC_MODE_SCANCODE: u32 = 0x0000_0008;
+const LIRC_SCANCODE_FLAG_REPEAT: u16 = 0x0002;
+const LIRC_SCANCODE_SIZE: usize = 24;
+
+const EV_MSC: u16 = 0x04;
+const MSC_SCAN: u16 = 0x04;
+
+const REPEAT_WINDOW: Duration = Duration::from_millis(1000);
+
+#[derive(Deserialize, Clone)]
+pub struct IrConfig {
+    device: Option<String>,
+    actions: Option<BTreeMap<String, Action>>,
+}
+
+#[derive(Debug)]
+pub enum IrError {
+    InvalidCode(String),
+}
+
+impl std::error::Error for IrError {}
+
+impl std::fmt::Display for IrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IrError::InvalidCode(code) => write!(f, "Invalid IR code '{}', expected a hexadecimal scancode", code),
+        }
+    }
+}
+
+impl IrConfig {
+    pub fn actions(&self) -> impl Iterator<Item = &Action> {
+        return self.actions.iter().flat_map(|actions| actions.values());
+    }
+
+    // When the configuration loads, rather than when the receiver starts.
+    pub fn validate(&self) -> Result<(), IrError> {
+        for code in self.actions.iter().flat_map(|actions| actions.keys()) {
+            parse_code(code)?;
+        }
+        return Ok(());
+    }
+}
+
+enum Receiver {
+    Lirc(AsyncFd<File>),
+    Input(AsyncFd<File>),
+}
+
+// Both kinds of devices hand out whole events, so a read never returns a
+// partial one.
+async fn read_event(file: &AsyncFd<File>, buffer: &mut [u8]) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let mut guard = file.readable().await?;
+        match guard.try_io(|file| (&mut file.get_ref()).read(buffer)) {
+            Ok(Ok(read)) if read == buffer.len() => { return Ok(()); },
+            Ok(Ok(_)) => { return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))); },
+            Ok(Err(error)) => { return Err(Box::new(error)); },
+            Err(_would_block) => {},
+        };
+    }
+}
+
+impl Receiver {
+    fn open(device: &str) -> Result<Receiver, Box<dyn std::error::Error>> {
+        let file = std::fs::OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(device)?;
+        if !device.starts_with("/dev/lirc") {
+            return Ok(Receiver::Input(AsyncFd::new(file)?));
+        }
+        let mode: u32 = LIRC_MODE_SCANCODE;
+        if unsafe { libc::ioctl(file.as_raw_fd(), LIRC_SET_REC_MODE as _, &mode) } < 0 {
+            return Err(Box::new(std::io::Error::last_os_error()));
+        }
+        return Ok(Receiver::Lirc(AsyncFd::new(file)?));
+    }
+
+    async fn next_code(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        match self {
+            Receiver::Lirc(file) => loop {
+                // struct lirc_scancode { u64 timestamp; u16 flags; u16 rc_proto; u32 keycode; u64 scancode; }
+                let mut buffer = [0u8; LIRC_SCANCODE_SIZE];
+                read_event(file, &mut buffer).await?;
+                let flags = u16::from_ne_bytes([buffer[8], buffer[9]]);
+                if flags & LIRC_SCANCODE_FLAG_REPEAT != 0 {
+                    continue;
+                }
+                let mut scancode = [0u8; 8];
+                scancode.copy_from_slice(&buffer[16..24]);
+                return Ok(u64::from_ne_bytes(scancode));
+            },
+            Receiver::Input(file) => loop {
+                // struct input_event { struct timeval time; u16 type; u16 code; s32 value; }
+                let mut buffer = [0u8; std::mem::size_of::<libc::input_event>()];
+                read_event(file, &mut buffer).await?;
+                let offset = std::mem::size_of::<libc::timeval>();
+                let event_type = u16::from_ne_bytes([buffer[offset], buffer[offset + 1]]);
+                let event_code = u16::from_ne_bytes([buffer[offset + 2], buffer[offset + 3]]);
+                let value = u32::from_ne_bytes([buffer[offset + 4], buffer[offset + 5], buffer[offset + 6], buffer[offset + 7]]);
+                if event_type == EV_MSC && event_code == MSC_SCAN {
+                    return Ok(value as u64);
+                }
+            },
+        }
+    }
+}
+
+fn parse_code(code: &str) -> Result<u64, IrError> {
+    return u64::from_str_radix(code.trim_start_matches("0x"), 16)
+        .map_err(|_| IrError::InvalidCode(String::from(code)));
+}
+
+pub async fn ir_check(config: IrConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let mut actions = BTreeMap::new();
+    for (code, action) in config.actions.unwrap_or_default() {
+        actions.insert(parse_code(&code)?, action);
+    }
+    let device = config.device.unwrap_or_else(|| String::from("/dev/lirc0"));
+    let receiver = Receiver::open(&device)?;
+    log::info!("Listening for IR codes on {}", device);
+    let mut last_code: Option<(u64, Instant)> = None;
+    loop {
+        let code = receiver.next_code().await?;
+        let now = Instant::now();
+        // Remotes resend the code for as long as the key is held.
+        if let Some((last, at)) = last_code {
+            if last == code && now.duration_since(at) < REPEAT_WINDOW {
+                last_code = Some((code, now));
+                continue;
+            }
+        }
+        last_code = Some((code, now));
+        match actions.get(&code) {
+            Some(action) => {
+                log::info!("IR code {:#x} received, running {:?}", code, action);
+                action.run("ir", &state)?;
+            },
+            None => log::info!("IR code {:#x} received with no action configured", code),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(parse_code("0x45").unwrap(), 0x45);
+        assert_eq!(parse_code("ff02").unwrap(), 0xff02);
+        assert!(matches!(parse_code("0xpower"), Err(IrError::InvalidCode(code)) if code == "0xpower"));
+    }
+
+    #[test]
+    fn invalid_code_in_the_configuration() {
+        let config: IrConfig = toml::from_str("[actions]\n0x45 = \"poweroff\"\npower = \"reboot\"\n").unwrap();
+        assert!(matches!(config.validate(), Err(IrError::InvalidCode(code)) if code == "power"));
+        let config: IrConfig = toml::from_str("[actions]\n0x45 = \"poweroff\"\n").unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
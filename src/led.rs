@@ -0,0 +1,71 @@
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::mcu::LedMode;
+use crate::{schedule, SharedState};
+
+// Fan temperatures are only looked at this often, everything else wakes the
+// LED task up right away.
+const LED_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Clone)]
+pub struct NightConfig {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LedConfig {
+    brightness: Option<u8>,
+    night: Option<NightConfig>,
+    thermal_alarm: Option<bool>,
+    breathe_on_shutdown: Option<bool>,
+}
+
+// From the most to the least urgent: a shutdown, a fan above its critical
+// temperature, the brightness set through the control socket, the night and
+// the configured brightness.
+fn desired(config: &LedConfig, state: &SharedState) -> Result<(LedMode, u8), schedule::ScheduleError> {
+    let brightness = config.brightness.unwrap_or(100).min(100);
+    if config.breathe_on_shutdown.unwrap_or(true) && state.shutting_down.load(Ordering::SeqCst) {
+        return Ok((LedMode::Breathe, brightness));
+    }
+    if config.thermal_alarm.unwrap_or(true) && state.fans.lock().unwrap().values().any(|fan_state| fan_state.critical) {
+        return Ok((LedMode::Blink, 100));
+    }
+    if let Some(brightness) = *state.led_override.lock().unwrap() {
+        return Ok((LedMode::Steady, brightness));
+    }
+    if let Some(ref night) = config.night {
+        if schedule::in_window(&night.from, &night.to)? {
+            return Ok((LedMode::Steady, 0));
+        }
+    }
+    return Ok((LedMode::Steady, brightness));
+}
+
+pub async fn led_check(config: LedConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let mcu = match state.mcu.as_ref() {
+        Some(mcu) if mcu.has_registers() => mcu,
+        _ => {
+            log::warn!("The fan controller has no LED control, [led] is ignored");
+            return Ok(());
+        },
+    };
+    let mut current = None;
+    loop {
+        let led = desired(&config, &state)?;
+        if current != Some(led) {
+            log::debug!("LED set to {:?} at {}%", led.0, led.1);
+            mcu.set_led(led.0, led.1)?;
+            current = Some(led);
+        }
+        tokio::select! {
+            terminating = state.termination.wait(LED_POLL_INTERVAL) => if terminating {
+                return Ok(());
+            },
+            _ = state.led_changed.notified() => {},
+        };
+    }
+}
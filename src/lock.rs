@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+
+pub const DEFAULT_LOCK: &str = "/run/argononed.lock";
+
+#[derive(Debug)]
+pub enum LockError {
+    AlreadyRunning(String, Option<u32>),
+}
+
+impl std::error::Error for LockError {}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LockError::AlreadyRunning(path, Some(pid)) => write!(f, "Another daemon is already running as pid {}, it holds {}", pid, path),
+            LockError::AlreadyRunning(path, None) => write!(f, "Another daemon is already running, it holds {}", path),
+        }
+    }
+}
+
+// The lock is an flock on the file, which the kernel drops along with the
+// process however that ends, so a crashed daemon never leaves a lock behind.
+// The file itself stays and holds the pid of the daemon while it runs.
+pub struct InstanceLock {
+    file: File,
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    return contents.trim().parse::<u32>().ok();
+}
+
+impl InstanceLock {
+    pub fn acquire(path: &str) -> Result<InstanceLock, Box<dyn std::error::Error>> {
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                return Err(Box::new(LockError::AlreadyRunning(String::from(path), read_pid(&mut file))));
+            }
+            return Err(Box::new(error));
+        }
+        // A clean exit empties the file, a pid left in it is from a daemon
+        // that did not get to.
+        if let Some(pid) = read_pid(&mut file) {
+            log::warn!("Taking over {} from pid {}, which did not exit cleanly", path, pid);
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        return Ok(InstanceLock { file });
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
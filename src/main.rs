@@ -1,32 +1,493 @@
+#![allow(clippy::needless_return)]
+
 extern crate libc;
-extern crate signal_hook;
 
-use serde::Deserialize;
-use std::thread;
-use std::process::Command;
-use std::sync::atomic::{AtomicU8, Ordering};
-use rppal::gpio::{Gpio, Trigger, Level};
-use rppal::i2c::I2c;
-use signal_hook::iterator::Signals;
+mod action;
+mod alerts;
+mod cluster;
+mod control;
+mod curve;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod display;
+mod generate;
+mod hardware;
+mod health;
+mod history;
+mod hooks;
+mod import;
+mod inhibit;
+mod ir;
+mod led;
+mod lock;
+mod mailbox;
+mod mcu;
+mod persist;
+mod rtc;
+mod schedule;
+mod service;
+mod settings;
+mod stats;
+mod status;
+mod steps;
+mod sysfs;
+mod system;
+mod trips;
+mod ups;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use hardware::{Gpio, I2c, Level, Trigger};
+use steps::{clamp_speed, evaluate_curve, quantize_speed, TempSpeedPair};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, Notify};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path to the configuration file
+    #[arg(short, long, env = "ARGONONED_CONFIG", default_value = "/etc/argononed.conf")]
+    config: String,
+    /// Format of the configuration file, guessed from its extension if unset
+    #[arg(long, value_enum)]
+    format: Option<ConfigFormat>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    // Anything that is not obviously YAML or JSON is read as TOML, like the
+    // default /etc/argononed.conf.
+    fn from_path(path: &str) -> ConfigFormat {
+        return match std::path::Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        };
+    }
+
+    fn name(self) -> &'static str {
+        return match self {
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Yaml => "yaml",
+            ConfigFormat::Json => "json",
+        };
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run the fan and power button daemon (the default)
+    Daemon,
+    /// Manage the RTC of the Argon EON
+    Rtc {
+        #[command(subcommand)]
+        command: rtc::RtcCommand,
+    },
+    /// Write, enable and start a systemd unit running the daemon with this configuration
+    InstallService,
+    /// Stop, disable and remove the systemd unit
+    UninstallService,
+    /// Tell the fan controller to cut the power, then power the system off
+    Poweroff,
+    /// Reboot the system, keeping the fan controller powering the Pi
+    Reboot,
+    /// Print the speed every fan and profile of this configuration runs at for each temperature
+    Curve {
+        /// Print the curves as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+    /// Convert the temperature=speed configuration of the Argon Python daemon to this one
+    ImportConfig {
+        /// The configuration to convert, like the /etc/argononed.conf of the Python daemon
+        input: String,
+        /// Where to write the converted configuration, printed if unset
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Ask the running daemon whether it controls the fans, exiting with 0 if so or the code of the problem
+    ///
+    /// Exits with 0 if healthy, 1 if the command itself failed, 2 for invalid
+    /// arguments, and otherwise with the code of the most serious problem: 3
+    /// unreachable, 4 stalled, 5 stale_temperature, 6 i2c_write.
+    Health,
+    /// Check the configuration without running the daemon
+    Check {
+        /// Print the result as a JSON document
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the completions of these arguments for a shell
+    Completions { shell: generate::Shell },
+    /// Print the man page of these arguments in roff
+    Manpage,
+}
 
 #[derive(Deserialize)]
+struct Config {
+    #[serde(flatten)]
+    default_fan: FanConfig,
+    fan: Option<BTreeMap<String, FanConfig>>,
+    ir: Option<ir::IrConfig>,
+    display: Option<display::DisplayConfig>,
+    rtc: Option<rtc::RtcConfig>,
+    profile: Option<BTreeMap<String, FanConfig>>,
+    schedule: Option<Vec<schedule::ScheduleEntry>>,
+    control: Option<control::ControlConfig>,
+    button: Option<ButtonConfig>,
+    startup: Option<StartupConfig>,
+    ups: Option<ups::UpsConfig>,
+    power: Option<mcu::PowerConfig>,
+    led: Option<led::LedConfig>,
+    persist: Option<persist::PersistConfig>,
+    #[cfg(feature = "dashboard")]
+    dashboard: Option<dashboard::DashboardConfig>,
+    history: Option<history::HistoryConfig>,
+    hooks: Option<hooks::HooksConfig>,
+    alerts: Option<alerts::AlertsConfig>,
+    inhibit: Option<inhibit::InhibitConfig>,
+    cluster: Option<cluster::ClusterConfig>,
+    i2c: Option<I2cConfig>,
+    system: Option<system::SystemConfig>,
+    hardware: Option<hardware::HardwareConfig>,
+    units: Option<Units>,
+}
+
+// Temperatures are handled in Celsius, the units only apply to what the
+// configuration gives and what is shown.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Units {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl Units {
+    fn to_celsius(self, temperature: f32) -> f32 {
+        return match self {
+            Units::Celsius => temperature,
+            Units::Fahrenheit => (temperature - 32.0) * 5.0 / 9.0,
+        };
+    }
+
+    // For offsets and ranges, which do not move with the zero point.
+    fn difference_to_celsius(self, difference: f32) -> f32 {
+        return match self {
+            Units::Celsius => difference,
+            Units::Fahrenheit => difference * 5.0 / 9.0,
+        };
+    }
+
+    fn convert_celsius(self, temperature: f32) -> f32 {
+        return match self {
+            Units::Celsius => temperature,
+            Units::Fahrenheit => temperature * 9.0 / 5.0 + 32.0,
+        };
+    }
+
+    fn symbol(self) -> &'static str {
+        return match self {
+            Units::Celsius => "C",
+            Units::Fahrenheit => "F",
+        };
+    }
+
+    fn format(self, temperature: f32) -> String {
+        return format!("{:.1}{}", self.convert_celsius(temperature), self.symbol());
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+struct StartupConfig {
+    lock_file: Option<String>,
+    timeout: Option<u64>,
+    self_test: Option<bool>,
+    self_test_duration: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+struct ButtonConfig {
+    pin: Option<u8>,
+    mode: Option<ButtonMode>,
+    window: Option<u64>,
+    glitch_filter: Option<u64>,
+    train_gap: Option<u64>,
+    pulses: Option<Vec<PulsePattern>>,
+    long_press_time: Option<u64>,
+    double_tap_time: Option<u64>,
+    gestures: Option<Gestures>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ButtonMode {
+    Pulse,
+    Raw,
+}
+
+#[derive(Deserialize, Clone, Default)]
+struct Gestures {
+    tap: Option<action::Action>,
+    double_tap: Option<action::Action>,
+    long_press: Option<action::Action>,
+}
+
+// Pulses at least min and less than max milliseconds wide run the action.
+#[derive(Deserialize, Clone)]
+struct PulsePattern {
+    min: u64,
+    max: u64,
+    action: action::Action,
+}
+
+#[derive(Deserialize, Clone, Default)]
+struct I2cConfig {
+    bus: Option<u8>,
+    address: Option<u16>,
+    protocol: Option<mcu::Protocol>,
+    retries: Option<u32>,
+    retry_delay: Option<u64>,
+    max_failures: Option<u32>,
+    fallback_speed: Option<u8>,
+    refresh_interval: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+struct I2cSettings {
+    address: u16,
+    register: u8,
+    retries: u32,
+    retry_delay: u64,
+    max_failures: u32,
+    fallback_speed: u8,
+    refresh_interval: u64,
+}
+
+impl From<I2cConfig> for I2cSettings {
+    fn from(config: I2cConfig) -> I2cSettings {
+        return I2cSettings {
+            address: config.address.unwrap_or(FAN_ADDR),
+            register: config.protocol.unwrap_or(mcu::Protocol::Raw).speed_register(),
+            retries: config.retries.unwrap_or(3),
+            retry_delay: config.retry_delay.unwrap_or(50),
+            max_failures: config.max_failures.unwrap_or(5),
+            fallback_speed: config.fallback_speed.unwrap_or(100),
+            refresh_interval: config.refresh_interval.unwrap_or(300),
+        };
+    }
+}
+
+#[derive(Deserialize, Clone)]
 struct FanConfig {
-    dynamic: bool,
+    dynamic: Option<bool>,
     const_fan_speed: Option<u8>,
     step: Option<Vec<TempSpeedPair>>,
     delay_on_change: Option<u64>,
+    register: Option<u8>,
+    rpm_register: Option<u8>,
+    sources: Option<Vec<TemperatureSource>>,
+    kick_start_speed: Option<u8>,
+    kick_start_duration: Option<u64>,
+    min_speed: Option<u8>,
+    max_speed: Option<u8>,
+    critical_temperature: Option<i16>,
+    shutdown_temperature: Option<i16>,
+    throttle_boost_speed: Option<u8>,
+    exit_fan_speed: Option<ExitFanSpeed>,
+    ramp_rate: Option<u8>,
+    max_sensor_failures: Option<u32>,
+    fallback_speed: Option<u8>,
+    max_poll_interval: Option<u64>,
+    stable_temperature: Option<f32>,
+    hysteresis: Option<f32>,
+    boot_boost: Option<BootBoost>,
+    speed_granularity: Option<u8>,
+    trip_points: Option<bool>,
+    thermal_zone: Option<String>,
 }
 
-#[derive(Deserialize)]
-struct TempSpeedPair {
-    temperature: i16,
-    fan_speed: u8,
+impl FanConfig {
+    fn inherit(self, parent: &FanConfig) -> FanConfig {
+        return FanConfig {
+            dynamic: self.dynamic.or(parent.dynamic),
+            const_fan_speed: self.const_fan_speed.or(parent.const_fan_speed),
+            step: self.step.or_else(|| parent.step.clone()),
+            delay_on_change: self.delay_on_change.or(parent.delay_on_change),
+            register: self.register.or(parent.register),
+            rpm_register: self.rpm_register.or(parent.rpm_register),
+            sources: self.sources.or_else(|| parent.sources.clone()),
+            kick_start_speed: self.kick_start_speed.or(parent.kick_start_speed),
+            kick_start_duration: self.kick_start_duration.or(parent.kick_start_duration),
+            min_speed: self.min_speed.or(parent.min_speed),
+            max_speed: self.max_speed.or(parent.max_speed),
+            critical_temperature: self.critical_temperature.or(parent.critical_temperature),
+            shutdown_temperature: self.shutdown_temperature.or(parent.shutdown_temperature),
+            throttle_boost_speed: self.throttle_boost_speed.or(parent.throttle_boost_speed),
+            exit_fan_speed: self.exit_fan_speed.or(parent.exit_fan_speed),
+            ramp_rate: self.ramp_rate.or(parent.ramp_rate),
+            max_sensor_failures: self.max_sensor_failures.or(parent.max_sensor_failures),
+            fallback_speed: self.fallback_speed.or(parent.fallback_speed),
+            max_poll_interval: self.max_poll_interval.or(parent.max_poll_interval),
+            stable_temperature: self.stable_temperature.or(parent.stable_temperature),
+            hysteresis: self.hysteresis.or(parent.hysteresis),
+            boot_boost: self.boot_boost.or(parent.boot_boost),
+            speed_granularity: self.speed_granularity.or(parent.speed_granularity),
+            trip_points: self.trip_points.or(parent.trip_points),
+            thermal_zone: self.thermal_zone.or_else(|| parent.thermal_zone.clone()),
+        };
+    }
+
+    fn normalize_units(mut self, units: Units) -> FanConfig {
+        let convert = |temperature: i16| units.to_celsius(temperature as f32).round() as i16;
+        if let Some(ref mut step) = self.step {
+            for pair in step.iter_mut() {
+                pair.temperature = convert(pair.temperature);
+            }
+        }
+        if let Some(ref mut sources) = self.sources {
+            for source in sources.iter_mut() {
+                source.offset = source.offset.map(|offset| units.difference_to_celsius(offset));
+                for pair in source.step.iter_mut().flatten() {
+                    pair.temperature = convert(pair.temperature);
+                }
+            }
+        }
+        self.critical_temperature = self.critical_temperature.map(convert);
+        self.shutdown_temperature = self.shutdown_temperature.map(convert);
+        self.stable_temperature = self.stable_temperature.map(|stable_temperature| units.difference_to_celsius(stable_temperature));
+        self.hysteresis = self.hysteresis.map(|hysteresis| units.difference_to_celsius(hysteresis));
+        return self;
+    }
+
+    // Applied once a fan is resolved rather than through serde, so that unset
+    // fields can still be inherited from the top level and overlaid by
+    // profiles.
+    fn with_defaults(mut self, name: &str, units: Units) -> FanConfig {
+        if self.dynamic.is_none() {
+            log::warn!("No dynamic given for fan '{}', defaulting to true", name);
+            self.dynamic = Some(true);
+        }
+        if self.dynamic == Some(true) && self.step.is_none() {
+            log::warn!("No step given for fan '{}', using the default curve", name);
+            self.step = Some(default_curve());
+        }
+        self.delay_on_change.get_or_insert(DEFAULT_DELAY_ON_CHANGE);
+        if self.trip_points == Some(true) {
+            self.apply_trip_points(name, units);
+        }
+        return self;
+    }
+
+    // The thresholds the configuration leaves unset follow the trip points
+    // of the kernel: full speed where it starts throttling, and a clean power
+    // off where it would cut it short.
+    fn apply_trip_points(&mut self, name: &str, units: Units) {
+        let zone = self.thermal_zone.clone().unwrap_or_else(|| String::from(trips::DEFAULT_THERMAL_ZONE));
+        let trip_points = match trips::read_trip_points(&zone) {
+            Ok(trip_points) => trip_points,
+            Err(error) => {
+                log::warn!("Fan '{}': failed to read the trip points of {}: {}", name, zone, error);
+                return;
+            },
+        };
+        if let (None, Some(throttle)) = (self.critical_temperature, trip_points.throttle) {
+            log::info!("Fan '{}': critical temperature at {} from the trip points of {}", name, units.format(throttle as f32), zone);
+            self.critical_temperature = Some(throttle);
+        }
+        if let (None, Some(critical)) = (self.shutdown_temperature, trip_points.critical) {
+            log::info!("Fan '{}': shutdown temperature at {} from the trip points of {}", name, units.format(critical as f32), zone);
+            self.shutdown_temperature = Some(critical);
+        }
+    }
+}
+
+// A step applies below its temperature, so the last one covers everything
+// from 65C on.
+fn default_curve() -> Vec<TempSpeedPair> {
+    return vec![
+        TempSpeedPair { temperature: 55, fan_speed: 0 },
+        TempSpeedPair { temperature: 60, fan_speed: 10 },
+        TempSpeedPair { temperature: 65, fan_speed: 55 },
+        TempSpeedPair { temperature: i16::MAX, fan_speed: 100 },
+    ];
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct BootBoost {
+    speed: Option<u8>,
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum ExitFanSpeed {
+    Speed(u8),
+    Hold(Hold),
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Hold {
+    Hold,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SensorKind {
+    Cpu,
+    Firmware,
+    File { path: String },
+    Cluster,
+}
+
+// Readings are corrected to reading * scale + offset before anything else
+// looks at them. A source with steps of its own is driven by them instead of
+// the curve of the fan, and slows down after its own delay.
+#[derive(Deserialize, Clone)]
+struct TemperatureSource {
+    #[serde(flatten)]
+    kind: SensorKind,
+    offset: Option<f32>,
+    scale: Option<f32>,
+    step: Option<Vec<TempSpeedPair>>,
+    delay_on_change: Option<u64>,
+}
+
+impl TemperatureSource {
+    fn describe(&self) -> String {
+        return match self.kind {
+            SensorKind::Cpu => String::from("cpu"),
+            SensorKind::Firmware => String::from("firmware"),
+            SensorKind::File { ref path } => format!("file {}", path),
+            SensorKind::Cluster => String::from("cluster"),
+        };
+    }
 }
 
 #[derive(Debug)]
 enum ConfigError {
-    NoConstantSpeed,
-    EmptyStepConfig,
+    NoDynamic(String),
+    NoConstantSpeed(String),
+    EmptyStepConfig(String),
+    InvalidSpeedRange(String),
+    InvalidGranularity(String),
+    Profile(String, Box<ConfigError>),
+    Parse(String, String),
+    InvalidEnvironment(&'static str, String),
 }
 
 impl std::error::Error for ConfigError {}
@@ -34,136 +495,1603 @@ impl std::error::Error for ConfigError {}
 impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            ConfigError::NoConstantSpeed => write!(f, "No const_fan_speed given when dynamic fan speed is set to false"),
-            ConfigError::EmptyStepConfig => write!(f, "Empty temperature-fanspeed step configuration"),
+            ConfigError::NoDynamic(fan) => write!(f, "No dynamic given for fan '{}'", fan),
+            ConfigError::NoConstantSpeed(fan) => write!(f, "No const_fan_speed given for fan '{}' when dynamic fan speed is set to false", fan),
+            ConfigError::EmptyStepConfig(fan) => write!(f, "Empty temperature-fanspeed step configuration for fan '{}'", fan),
+            ConfigError::InvalidSpeedRange(fan) => write!(f, "min_speed is greater than max_speed for fan '{}'", fan),
+            ConfigError::InvalidGranularity(fan) => write!(f, "speed_granularity of fan '{}' is not between 1 and 100", fan),
+            ConfigError::Profile(profile, error) => write!(f, "In profile '{}': {}", profile, error),
+            ConfigError::Parse(filename, error) => write!(f, "Invalid configuration in {}: {}", filename, error),
+            ConfigError::InvalidEnvironment(variable, value) => write!(f, "Invalid value '{}' for {}", value, variable),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct FanState {
+    temperature: Option<f32>,
+    // Where the temperature settled, which only follows the readings once they
+    // move by more than stable_temperature.
+    smoothed_temperature: Option<f32>,
+    target_speed: u8,
+    fan_speed: u8,
+    rpm: Option<u16>,
+    profile: Option<String>,
+    critical: bool,
+}
+
+// Tasks wait on this instead of sleeping, so that they stay idle between
+// events and still wake up as soon as the daemon is asked to stop.
+struct Termination {
+    requested: watch::Sender<bool>,
+}
+
+impl Default for Termination {
+    fn default() -> Termination {
+        return Termination { requested: watch::channel(false).0 };
+    }
+}
+
+impl Termination {
+    fn request(&self) {
+        self.requested.send_replace(true);
+    }
+
+    async fn requested(&self) {
+        let _ = self.requested.subscribe().wait_for(|requested| *requested).await;
+    }
+
+    // Returns whether termination was requested before the timeout.
+    async fn wait(&self, timeout: Duration) -> bool {
+        return tokio::time::timeout(timeout, self.requested()).await.is_ok();
+    }
+}
+
+// A speed forced through the control socket, for good or until it expires.
+#[derive(Clone, Copy)]
+struct SpeedOverride {
+    speed: u8,
+    until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    fans: Mutex<BTreeMap<String, FanState>>,
+    profile_override: Mutex<Option<String>>,
+    speed_override: Mutex<Option<SpeedOverride>>,
+    battery: Mutex<Option<ups::BatteryState>>,
+    speed_override_changed: Notify,
+    stats: Arc<stats::Stats>,
+    last_button_pulse: AtomicU64,
+    display_off: AtomicBool,
+    display_toggled: Notify,
+    led_override: Mutex<Option<u8>>,
+    led_changed: Notify,
+    shutting_down: AtomicBool,
+    shutdown_pending: AtomicBool,
+    shutdown_lock: Mutex<Option<std::process::Child>>,
+    hooks: hooks::HooksConfig,
+    system: Box<dyn system::SystemActions>,
+    inhibit: inhibit::InhibitConfig,
+    events: hooks::EventStream,
+    health: health::Health,
+    settings: settings::Settings,
+    alerts: alerts::Alerts,
+    cluster: cluster::Cluster,
+    mcu: Option<mcu::Mcu>,
+    started_at: Option<Instant>,
+    units: Units,
+    termination: Termination,
+}
+
+impl State {
+    // Hooks and subscribers on the control socket see the same events.
+    fn emit(&self, event: hooks::Event) {
+        self.events.publish(&event);
+        self.hooks.run(event, self.system.as_ref());
+    }
+
+    // Expired overrides are dropped on the way, whoever looks first.
+    fn active_speed_override(&self) -> Option<SpeedOverride> {
+        let mut speed_override = self.speed_override.lock().unwrap();
+        if speed_override.is_some_and(|speed_override| speed_override.until.is_some_and(|until| Instant::now() >= until)) {
+            log::info!("Fan speed override expired, back to automatic control");
+            *speed_override = None;
         }
+        return *speed_override;
+    }
+
+    // A new speed override cuts the wait short so that it applies right away.
+    async fn wait_for_change(&self, timeout: Duration) -> Wake {
+        return tokio::select! {
+            terminating = self.termination.wait(timeout) => match terminating {
+                true => Wake::Termination,
+                false => Wake::Timeout,
+            },
+            _ = self.speed_override_changed.notified() => Wake::SpeedOverrideChanged,
+        };
     }
 }
 
+type SharedState = Arc<State>;
+
 const FAN_ADDR: u16 = 0x1a;
+const CPU_THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0/temp";
+const DEFAULT_DELAY_ON_CHANGE: u64 = 30;
+const CRITICAL_POLL_INTERVAL: u64 = 5;
+const SENSOR_RETRY_INTERVAL: u64 = 5;
+const DECAY_POLL_INTERVAL: u64 = 5;
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(20);
+const RESTART_DELAY_MIN: u64 = 1;
+const RESTART_DELAY_MAX: u64 = 60;
+
+const THROTTLED_UNDERVOLTAGE: u32 = 0x1;
+const THROTTLED_FREQUENCY_CAPPED: u32 = 0x2;
+const THROTTLED_THROTTLING: u32 = 0x4;
+const THROTTLED_SOFT_TEMPERATURE_LIMIT: u32 = 0x8;
+const THROTTLED_THERMAL: u32 = THROTTLED_FREQUENCY_CAPPED | THROTTLED_THROTTLING | THROTTLED_SOFT_TEMPERATURE_LIMIT;
 
-fn shutdown_check(gpio_interface: Gpio, shutdown_pin_loc: u8) -> Result<(), Box<dyn std::error::Error>> {
-    let mut signals = Signals::new(&[
-        signal_hook::SIGTERM,
-        signal_hook::SIGINT,
-        signal_hook::SIGQUIT,
-    ])?;
-    let mut shutdown_pin = gpio_interface.get(shutdown_pin_loc)?.into_input_pulldown();
-    static PULSE_TIME: AtomicU8 = AtomicU8::new(0);
-    shutdown_pin.set_async_interrupt(Trigger::RisingEdge, |level| {
-        match level {
-            Level::Low => {},
-            Level::High => { PULSE_TIME.fetch_add(1, Ordering::SeqCst); },
+fn default_pulses() -> Vec<PulsePattern> {
+    return vec![
+        PulsePattern { min: 20, max: 40, action: action::Action::Reboot },
+        PulsePattern { min: 40, max: 60, action: action::Action::Poweroff },
+    ];
+}
+
+// Collects the edges from a rising edge until the pin stayed low for the
+// train gap, and returns the time from the first rising to the last falling
+// edge, or None once the edges stop. Two edges of the same level in a row mean
+// the glitch filter dropped the one in between: a second rising edge starts the
+// train over, a second falling edge ends a dropout within the pulse.
+async fn next_train(edges: &mut mpsc::UnboundedReceiver<(Level, Instant)>, window: Duration, train_gap: Duration) -> Option<Duration> {
+    let mut started = loop {
+        if let (Level::High, at) = edges.recv().await? {
+            break at;
+        }
+    };
+    let mut ended: Option<Instant> = None;
+    loop {
+        let deadline = match ended {
+            Some(ended) => ended + train_gap,
+            None => started + window + train_gap,
         };
-    })?;
+        match tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), edges.recv()).await {
+            Ok(None) => { return None; },
+            Ok(Some((Level::High, at))) => match ended {
+                Some(_) => { ended = None; },
+                None => { started = at; },
+            },
+            Ok(Some((Level::Low, at))) => { ended = Some(at); },
+            Err(_) => {
+                return Some(match ended {
+                    Some(ended) => ended.duration_since(started),
+                    None => deadline.duration_since(started),
+                });
+            },
+        };
+    }
+}
+
+// The case tells what happened to the button through the width of a single
+// pulse, 20-30ms after a double tap and 40-50ms after a long press. Every train
+// of pulses is decided on its own, trains longer than the window are noise.
+async fn decode_pulses(mut edges: mpsc::UnboundedReceiver<(Level, Instant)>, config: ButtonConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let window = Duration::from_millis(config.window.unwrap_or(100));
+    let train_gap = Duration::from_millis(config.train_gap.unwrap_or(10));
+    let pulses = config.pulses.unwrap_or_else(default_pulses);
+    while let Some(width) = next_train(&mut edges, window, train_gap).await {
+        if width > window {
+            log::debug!("Ignoring a {}ms button pulse, longer than the window", width.as_millis());
+            continue;
+        }
+        let width = width.as_millis() as u64;
+        state.last_button_pulse.store(width, Ordering::SeqCst);
+        state.emit(hooks::Event::Button { width });
+        match pulses.iter().find(|pulse| pulse.min <= width && width < pulse.max) {
+            Some(pulse) => {
+                log::info!("Button pulse of {}ms, running {:?}", width, pulse.action);
+                pulse.action.run("button", &state)?;
+            },
+            None => log::info!("Button pulse of {}ms with no action configured", width),
+        };
+    }
+    return Ok(());
+}
 
-    'outer: loop {
-        for signal in signals.pending() {
-            match signal as libc::c_int {
-                signal_hook::SIGTERM | signal_hook::SIGINT | signal_hook::SIGQUIT => {
-                    break 'outer;
+// Waits for the pin to reach the level, skipping the bounces of a mechanical
+// button.
+async fn next_edge(edges: &mut mpsc::UnboundedReceiver<(Level, Instant)>, level: Level, last_edge: &mut Instant) -> Option<Instant> {
+    while let Some((edge_level, at)) = edges.recv().await {
+        if edge_level == level && at.duration_since(*last_edge) >= BUTTON_DEBOUNCE {
+            *last_edge = at;
+            return Some(at);
+        }
+    }
+    return None;
+}
+
+// A button wired straight to the pin, pressed while high. A press is a long
+// press once held for long_press_time, otherwise it is a tap, or a double tap
+// when pressed again within double_tap_time.
+async fn decode_gestures(mut edges: mpsc::UnboundedReceiver<(Level, Instant)>, config: ButtonConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let long_press_time = Duration::from_millis(config.long_press_time.unwrap_or(1000));
+    let double_tap_time = Duration::from_millis(config.double_tap_time.unwrap_or(400));
+    let gestures = config.gestures.unwrap_or_default();
+    let mut last_edge = Instant::now();
+    loop {
+        let pressed = match next_edge(&mut edges, Level::High, &mut last_edge).await {
+            Some(at) => at,
+            None => { return Ok(()); },
+        };
+        let released = match next_edge(&mut edges, Level::Low, &mut last_edge).await {
+            Some(at) => at,
+            None => { return Ok(()); },
+        };
+        let (gesture, action) = match released.duration_since(pressed) >= long_press_time {
+            true => ("long_press", &gestures.long_press),
+            false => match tokio::time::timeout(double_tap_time, next_edge(&mut edges, Level::High, &mut last_edge)).await {
+                Ok(Some(_)) => {
+                    if next_edge(&mut edges, Level::Low, &mut last_edge).await.is_none() {
+                        return Ok(());
+                    }
+                    ("double_tap", &gestures.double_tap)
                 },
-                _ => unreachable!(),
-            }
+                Ok(None) => { return Ok(()); },
+                Err(_) => ("tap", &gestures.tap),
+            },
         };
-        match PULSE_TIME.load(Ordering::SeqCst) {
-            2 | 3 => { Command::new("systemctl reboot").spawn()?; },
-            4 | 5 => { Command::new("systemctl poweroff").spawn()?; },
-            _ => {},
+        state.emit(hooks::Event::Gesture { gesture });
+        match action {
+            Some(action) => {
+                log::info!("Button {}, running {:?}", gesture, action);
+                action.run("button", &state)?;
+            },
+            None => log::info!("Button {} with no action configured", gesture),
         };
+    }
+}
+
+async fn shutdown_check(gpio_interface: Gpio, config: ButtonConfig, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let mut shutdown_pin = gpio_interface.get(config.pin.unwrap_or(4))?.into_input_pulldown();
+    let (edge_sender, edges) = mpsc::unbounded_channel();
+    // Noise on the pin shows up as edges only microseconds apart. The edge
+    // ending such a glitch is dropped, the decoders cope with the one left.
+    let glitch_filter = Duration::from_micros(config.glitch_filter.unwrap_or(1000));
+    let mut last_edge: Option<Instant> = None;
+    let stats = Arc::clone(&state.stats);
+    let mut storm_window = (Instant::now(), 0);
+    shutdown_pin.set_async_interrupt(Trigger::Both, move |level| {
+        stats.edge(&mut storm_window);
+        let now = Instant::now();
+        let glitch = matches!(last_edge, Some(last_edge) if now.duration_since(last_edge) < glitch_filter);
+        last_edge = Some(now);
+        if !glitch {
+            let _ = edge_sender.send((level, now));
+        }
+    })?;
+    return match config.mode.unwrap_or(ButtonMode::Pulse) {
+        ButtonMode::Pulse => decode_pulses(edges, config, state).await,
+        ButtonMode::Raw => decode_gestures(edges, config, state).await,
     };
+}
+
+// A missing or empty file is the same as a file with nothing set, every fan
+// then runs on the defaults.
+fn load_config(filename: &str, format: Option<ConfigFormat>) -> Result<Config, Box<dyn std::error::Error>> {
+    let contents = match std::fs::read_to_string(filename) {
+        Ok(contents) => contents,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+            log::warn!("{} not found, using the default configuration", filename);
+            String::new()
+        },
+        Err(error) => { return Err(Box::new(error)); },
+    };
+    let parsed: Result<Config, Box<dyn std::error::Error>> = match format.unwrap_or_else(|| ConfigFormat::from_path(filename)) {
+        _ if contents.trim().is_empty() => toml::from_str("").map_err(Box::from),
+        ConfigFormat::Toml => toml::from_str(&contents).map_err(Box::from),
+        ConfigFormat::Yaml => serde_yaml::from_str(&contents).map_err(Box::from),
+        ConfigFormat::Json => serde_json::from_str(&contents).map_err(Box::from),
+    };
+    let mut config = parsed.map_err(|error| ConfigError::Parse(String::from(filename), error.to_string()))?;
+    if let Some(ref mut step) = config.default_fan.step {
+        step.sort_by_key(|pair| pair.temperature);
+    };
+    for fans in config.fan.iter_mut().chain(config.profile.iter_mut()) {
+        for fan in fans.values_mut() {
+            if let Some(ref mut step) = fan.step {
+                step.sort_by_key(|pair| pair.temperature);
+            };
+        }
+    }
+    if let Some(ref schedule) = config.schedule {
+        schedule::validate(schedule, config.profile.as_ref())?;
+    };
+    if let Some(ref button) = config.button {
+        let gestures = button.gestures.iter().flat_map(|gestures| [&gestures.tap, &gestures.double_tap, &gestures.long_press]).flatten();
+        let pulses = button.pulses.iter().flatten().map(|pulse| &pulse.action);
+        action::validate(gestures.chain(pulses), config.profile.as_ref())?;
+    };
+    if let Some(ref ir) = config.ir {
+        ir.validate()?;
+        action::validate(ir.actions(), config.profile.as_ref())?;
+    };
+    if let Some(ref display) = config.display {
+        display.validate()?;
+    };
+    return Ok(config);
+}
+
+fn parse_address(address: &str) -> Option<u16> {
+    return match address.strip_prefix("0x") {
+        Some(hexadecimal) => u16::from_str_radix(hexadecimal, 16).ok(),
+        None => address.parse::<u16>().ok(),
+    };
+}
+
+// Environment variables win over the configuration file, which is how
+// containers and NixOS prefer to configure daemons.
+fn apply_environment(config: &mut Config) -> Result<(), ConfigError> {
+    if let Ok(pin) = std::env::var("ARGONONED_BUTTON_PIN") {
+        let parsed = pin.parse::<u8>().map_err(|_| ConfigError::InvalidEnvironment("ARGONONED_BUTTON_PIN", pin.clone()))?;
+        config.button.get_or_insert_with(ButtonConfig::default).pin = Some(parsed);
+    }
+    if let Ok(address) = std::env::var("ARGONONED_I2C_ADDRESS") {
+        let parsed = parse_address(&address).ok_or_else(|| ConfigError::InvalidEnvironment("ARGONONED_I2C_ADDRESS", address.clone()))?;
+        config.i2c.get_or_insert_with(I2cConfig::default).address = Some(parsed);
+    }
+    if let Ok(bus) = std::env::var("ARGONONED_I2C_BUS") {
+        let parsed = bus.parse::<u8>().map_err(|_| ConfigError::InvalidEnvironment("ARGONONED_I2C_BUS", bus.clone()))?;
+        config.i2c.get_or_insert_with(I2cConfig::default).bus = Some(parsed);
+    }
     return Ok(());
 }
 
-fn load_config(filename: &str) -> Result<FanConfig, Box<dyn std::error::Error>> {
-    let mut fanconfig: FanConfig = toml::from_str::<FanConfig>(&std::fs::read_to_string(filename)?[..])?;
-    match fanconfig.step {
-        Some(ref mut step) => { step.sort_by(|a, b| a.temperature.cmp(&b.temperature)); },
-        None => {},
-    };
-    return Ok(fanconfig);
-}
-
-fn read_temperature() -> Result<f32, Box<dyn std::error::Error>> {
-    return Ok(std::str::from_utf8(&Command::new("/opt/vc/bin/vcgencmd")
-            .arg("measure_temp")
-            .output()?
-            .stdout[..])?
-        .trim_start_matches("temp=")
-        .trim_end()
-        .trim_end_matches("\'C")
-        .parse::<f32>()?);
-}
-
-fn fan_check(i2c_interface: I2c) -> Result<(), Box<dyn std::error::Error>> {
-    let mut signals = Signals::new(&[
-        signal_hook::SIGTERM,
-        signal_hook::SIGINT,
-        signal_hook::SIGQUIT,
-    ])?;
-    let config = load_config("/etc/argononed.conf")?;
-    match config.dynamic {
-        false => {
-            match config.const_fan_speed {
-                Some(speed) => { i2c_interface.smbus_write_byte(0, speed)?; },
-                None => { return Err(std::boxed::Box::new(ConfigError::NoConstantSpeed)); },
-            }
+fn apply_units(config: &mut Config) {
+    let units = config.units.unwrap_or_default();
+    if units == Units::Celsius {
+        return;
+    }
+    config.default_fan = config.default_fan.clone().normalize_units(units);
+    for fan_configs in config.fan.iter_mut().chain(config.profile.iter_mut()) {
+        for fan_config in fan_configs.values_mut() {
+            *fan_config = fan_config.clone().normalize_units(units);
+        }
+    }
+}
+
+// Fans without a table of their own inherit every unset field from the
+// top-level configuration, so single-fan configs keep working unchanged.
+fn resolve_fans(config: Config) -> BTreeMap<String, FanConfig> {
+    let default_fan = config.default_fan;
+    let units = config.units.unwrap_or_default();
+    return match config.fan {
+        None => {
+            let mut fans = BTreeMap::new();
+            fans.insert(String::from("default"), default_fan.with_defaults("default", units));
+            fans
         },
-        true => {
-            let delay: u64 = match config.delay_on_change {
-                None => 30,
-                Some(delay) => delay,
+        Some(fans) => fans.into_iter().map(|(name, fan)| {
+            let fan = fan.inherit(&default_fan).with_defaults(&name, units);
+            (name, fan)
+        }).collect(),
+    };
+}
+
+// The kernel thermal zone of the CPU, or the reading of the firmware on
+// kernels without one.
+async fn read_cpu_temperature() -> Result<f32, Box<dyn std::error::Error>> {
+    return match sysfs::read_number(CPU_THERMAL_ZONE) {
+        Ok(temperature) => Ok(temperature / 1000.0),
+        Err(_) => read_firmware_temperature().await,
+    };
+}
+
+// The firmware reads the same sensor on the SoC die as the thermal zone of the
+// kernel, through the mailbox.
+async fn read_firmware_temperature() -> Result<f32, Box<dyn std::error::Error>> {
+    return mailbox::temperature();
+}
+
+// Only the bits for the current state are kept, the "has occurred" bits in
+// the upper half would otherwise stay set until the next reboot.
+async fn read_throttled() -> Result<u32, Box<dyn std::error::Error>> {
+    return match mailbox::throttled().map(|throttled| throttled & 0xf) {
+        Ok(throttled) => Ok(throttled),
+        Err(error) => read_hwmon_undervoltage().map_err(|_| error),
+    };
+}
+
+fn read_hwmon_undervoltage() -> Result<u32, Box<dyn std::error::Error>> {
+    for entry in std::fs::read_dir("/sys/class/hwmon")? {
+        let path = entry?.path();
+        if std::fs::read_to_string(path.join("name")).map(|name| name.trim() == "rpi_volt").unwrap_or(false) {
+            return match std::fs::read_to_string(path.join("in0_lcrit_alarm"))?.trim() {
+                "0" => Ok(0),
+                _ => Ok(THROTTLED_UNDERVOLTAGE),
             };
-            match config.step {
-                None => { return Err(std::boxed::Box::new(ConfigError::EmptyStepConfig)); },
-                Some(step_config) => {
-                    if step_config.len() == 0 {
-                        return Err(std::boxed::Box::new(ConfigError::EmptyStepConfig));
+        }
+    }
+    return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "No rpi_volt hwmon sensor")));
+}
+
+fn describe_throttled(throttled: u32) -> String {
+    let mut reasons = Vec::new();
+    if throttled & THROTTLED_UNDERVOLTAGE != 0 {
+        reasons.push("under-voltage");
+    }
+    if throttled & THROTTLED_FREQUENCY_CAPPED != 0 {
+        reasons.push("frequency capped");
+    }
+    if throttled & THROTTLED_THROTTLING != 0 {
+        reasons.push("throttled");
+    }
+    if throttled & THROTTLED_SOFT_TEMPERATURE_LIMIT != 0 {
+        reasons.push("soft temperature limit");
+    }
+    return match reasons.is_empty() {
+        true => String::from("none"),
+        false => reasons.join(", "),
+    };
+}
+
+// Boosting the fan only helps against the thermal causes of throttling, an
+// under-voltage is merely reported.
+fn apply_throttle_policy(name: &str, throttled: u32, last_throttled: u32, boost_speed: u8, speed: u8) -> u8 {
+    if throttled != last_throttled {
+        match throttled {
+            0 => log::info!("Fan '{}': firmware throttling cleared", name),
+            _ => log::warn!("Fan '{}': firmware reports {}", name, describe_throttled(throttled)),
+        };
+    }
+    return match throttled & THROTTLED_THERMAL {
+        0 => speed,
+        _ => speed.max(boost_speed),
+    };
+}
+
+async fn read_source_temperature(source: &TemperatureSource, state: &State) -> Result<f32, Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let temperature = match source.kind {
+        SensorKind::Cpu => read_cpu_temperature().await?,
+        SensorKind::Firmware => read_firmware_temperature().await?,
+        SensorKind::File { ref path } => sysfs::read_number(path)? / 1000.0,
+        SensorKind::Cluster => state.cluster.max_temperature()?,
+    };
+    if started.elapsed() > stats::SENSOR_TIMEOUT {
+        log::warn!("Reading the temperature from {} took {}ms", source.describe(), started.elapsed().as_millis());
+        stats::Stats::count(&state.stats.sensor_timeouts);
+    }
+    return Ok(temperature * source.scale.unwrap_or(1.0) + source.offset.unwrap_or(0.0));
+}
+
+// A source without a reading, a cluster whose peers went quiet for one, is
+// left out as long as another source has one. Only with none left does the
+// read fail, with the error of the last source.
+fn no_reading(error: Option<Box<dyn std::error::Error>>) -> Box<dyn std::error::Error> {
+    return error.unwrap_or_else(|| Box::from("No temperature source"));
+}
+
+// Into the buffer of the fan, which keeps its capacity from one cycle to the
+// next, None for the sources left out.
+async fn read_temperatures(name: &str, sources: &[TemperatureSource], state: &State, temperatures: &mut Vec<Option<f32>>) -> Result<(), Box<dyn std::error::Error>> {
+    temperatures.clear();
+    let mut last_error = None;
+    for source in sources.iter() {
+        match read_source_temperature(source, state).await {
+            Ok(temperature) => temperatures.push(Some(temperature)),
+            Err(error) => {
+                temperatures.push(None);
+                last_error = Some((source, error));
+            },
+        };
+    }
+    return match last_error {
+        Some((_, error)) if temperatures.iter().all(Option::is_none) => Err(error),
+        Some((source, error)) => {
+            stats::Stats::count(&state.stats.sensor_failures);
+            log::warn!("Fan '{}': leaving out {} this cycle: {}", name, source.describe(), error);
+            Ok(())
+        },
+        None => Ok(()),
+    };
+}
+
+async fn read_max_temperature(sources: &[TemperatureSource], state: &State) -> Result<f32, Box<dyn std::error::Error>> {
+    let mut temperature: Option<f32> = None;
+    let mut last_error = None;
+    for source in sources.iter() {
+        match read_source_temperature(source, state).await {
+            Ok(reading) => { temperature = Some(temperature.map_or(reading, |temperature| temperature.max(reading))); },
+            Err(error) => { last_error = Some(error); },
+        };
+    }
+    return temperature.ok_or_else(|| no_reading(last_error));
+}
+
+// The highest speed the sources ask for, straight from their readings.
+async fn read_max_target(fan_config: &FanConfig, sources: &[TemperatureSource], state: &State, speed: u8) -> Result<u8, Box<dyn std::error::Error>> {
+    let mut target = None;
+    let mut last_error = None;
+    for source in sources.iter() {
+        let reading = match read_source_temperature(source, state).await {
+            Ok(reading) => reading,
+            Err(error) => {
+                last_error = Some(error);
+                continue;
+            },
+        };
+        target = Some(match fan_config.dynamic {
+            Some(true) => target.unwrap_or(0).max(source_target(fan_config, source, reading, speed)),
+            _ => target_speed(fan_config, None, false),
+        });
+    }
+    return target.ok_or_else(|| no_reading(last_error));
+}
+
+// The bus is shared with other devices such as the OLED display, so the slave
+// address has to be selected again for every write.
+fn write_fan_speed(i2c_interface: &Mutex<I2c>, address: u16, register: u8, speed: u8) -> Result<(), Box<dyn std::error::Error>> {
+    let mut i2c_interface = i2c_interface.lock().unwrap();
+    i2c_interface.set_slave_address(address)?;
+    i2c_interface.smbus_write_byte(register, speed)?;
+    return Ok(());
+}
+
+fn read_fan_rpm(i2c_interface: &Mutex<I2c>, address: u16, register: u8) -> Result<u16, Box<dyn std::error::Error>> {
+    let mut i2c_interface = i2c_interface.lock().unwrap();
+    i2c_interface.set_slave_address(address)?;
+    return Ok(i2c_interface.smbus_read_word(register)?);
+}
+
+// The speed a validated fan configuration asks for, before throttling, boosts
+// and overrides.
+fn target_speed(fan_config: &FanConfig, temperature: Option<f32>, critical: bool) -> u8 {
+    return match (fan_config.dynamic, fan_config.const_fan_speed, &fan_config.step) {
+        _ if critical => 100,
+        (Some(true), _, Some(step_config)) => clamp_speed(
+            evaluate_curve(step_config, temperature.unwrap()),
+            fan_config.min_speed,
+            fan_config.max_speed,
+        ),
+        (_, Some(speed), _) => speed,
+        _ => unreachable!(),
+    };
+}
+
+// One target per source, in their order. Sources with steps of their own
+// follow them, the others the curve of the fan.
+// With hysteresis, a fan running at speed only slows down once the reading is
+// that many degrees below the step it would slow down to.
+fn source_target(fan_config: &FanConfig, source: &TemperatureSource, reading: f32, speed: u8) -> u8 {
+    let target = |reading: f32| match source.step {
+        Some(ref step) => clamp_speed(evaluate_curve(step, reading), fan_config.min_speed, fan_config.max_speed),
+        None => target_speed(fan_config, Some(reading), false),
+    };
+    return match fan_config.hysteresis.filter(|hysteresis| *hysteresis > 0.0) {
+        Some(hysteresis) => target(reading).max(target(reading + hysteresis).min(speed)),
+        None => target(reading),
+    };
+}
+
+// A source left out this cycle asks for nothing.
+fn source_targets(fan_config: &FanConfig, sources: &[TemperatureSource], readings: Option<&[Option<f32>]>, critical: bool, speed: u8, targets: &mut Vec<u8>) {
+    targets.clear();
+    match readings {
+        Some(readings) if fan_config.dynamic == Some(true) && !critical => {
+            targets.extend(sources.iter().zip(readings.iter()).map(|(source, reading)| reading.map_or(0, |reading| source_target(fan_config, source, reading, speed))));
+        },
+        _ => { targets.push(target_speed(fan_config, None, critical)); },
+    };
+}
+
+// Slowing down goes through every speed of the curves in between, one per
+// delay, so that a short dip in the temperature only costs one step.
+fn step_down(fan_config: &FanConfig, sources: &[TemperatureSource], speed: u8, target: u8) -> u8 {
+    if fan_config.dynamic != Some(true) {
+        return target;
+    }
+    return fan_config.step.iter().flatten()
+        .chain(sources.iter().flat_map(|source| source.step.iter().flatten()))
+        .map(|pair| clamp_speed(pair.fan_speed, fan_config.min_speed, fan_config.max_speed))
+        .filter(|step_speed| *step_speed < speed)
+        .max()
+        .map_or(target, |step_speed| step_speed.max(target));
+}
+
+struct Fan {
+    name: String,
+    i2c_interface: Arc<Mutex<I2c>>,
+    state: SharedState,
+    register: u8,
+    speed: u8,
+    kick_start: Option<(u8, u64)>,
+    speed_granularity: u8,
+    i2c_settings: I2cSettings,
+    failures: u32,
+    stalled: bool,
+    written_at: Option<Instant>,
+}
+
+impl Fan {
+    // Transient NACKs are retried with an exponential backoff before giving up.
+    async fn write(&self, speed: u8) -> Result<(), Box<dyn std::error::Error>> {
+        let mut retry_delay = self.i2c_settings.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match write_fan_speed(&self.i2c_interface, self.i2c_settings.address, self.register, speed) {
+                Ok(()) => {
+                    self.state.health.wrote(&self.name, true);
+                    return Ok(());
+                },
+                Err(error) => {
+                    if attempt >= self.i2c_settings.retries {
+                        stats::Stats::count(&self.state.stats.i2c_write_failures);
+                        self.state.health.wrote(&self.name, false);
+                        return Err(error);
                     }
-                    let mut curret_fan_speed: u8 = 0;
-                    'outer: loop {
-                        for signal in signals.pending() {
-                            match signal as libc::c_int {
-                                signal_hook::SIGTERM | signal_hook::SIGINT | signal_hook::SIGQUIT => {
-                                    i2c_interface.smbus_write_byte(0, 0)?;
-                                    break 'outer;
-                                },
-                                _ => unreachable!(),
-                            }
-                        };
-                        let current_temperature = read_temperature()?;
-                        let mut target_fan_speed: u8 = 0;
-                        for temperature_step in step_config.iter() {
-                            if current_temperature < (temperature_step.temperature as f32) {
-                                target_fan_speed = temperature_step.fan_speed;
-                                break;
-                            }
-                        }
-                        if target_fan_speed < curret_fan_speed {
-                            thread::sleep(std::time::Duration::from_secs(delay));
-                        }
-                        curret_fan_speed = target_fan_speed;
-                        i2c_interface.smbus_write_byte(0, curret_fan_speed)?;
-                        thread::sleep(std::time::Duration::from_secs(delay));
-                    };
+                    stats::Stats::count(&self.state.stats.i2c_retries);
                 },
             };
+            attempt += 1;
+            tokio::time::sleep(Duration::from_millis(retry_delay)).await;
+            retry_delay *= 2;
+        }
+    }
+
+    fn record_speed(&mut self, speed: u8) {
+        if speed != self.speed {
+            self.state.emit(hooks::Event::SpeedChange { fan: &self.name, old_speed: self.speed, speed });
+        }
+        self.speed = speed;
+    }
+
+    // Slews the written speed toward the target by at most ramp_rate percent
+    // every second instead of jumping there. Returns Wake::Timeout once the
+    // target is reached.
+    // Steps are whole multiples of the speed granularity, so that every step
+    // gets closer to the target.
+    async fn ramp_to(&mut self, target: u8, ramp_rate: Option<u8>) -> Wake {
+        let target = quantize_speed(target, self.speed_granularity);
+        let ramp_rate = ramp_rate.map(|ramp_rate| quantize_speed(ramp_rate, self.speed_granularity));
+        loop {
+            let speed = match ramp_rate {
+                Some(ramp_rate) if target > self.speed.saturating_add(ramp_rate) => quantize_speed(self.speed + ramp_rate, self.speed_granularity),
+                Some(ramp_rate) if target < self.speed.saturating_sub(ramp_rate) => quantize_speed(self.speed - ramp_rate, self.speed_granularity),
+                _ => target,
+            };
+            self.set_speed(speed).await;
+            // A failed write ends the ramp, the next cycle starts over from the
+            // speed that was written last.
+            if speed == target || self.speed != speed {
+                return Wake::Timeout;
+            }
+            match self.state.wait_for_change(Duration::from_secs(1)).await {
+                Wake::Timeout => {},
+                wake => { return wake; },
+            };
+        }
+    }
+
+    // Low duty cycles may not be enough to get a stopped fan turning, so it is
+    // briefly driven harder before settling at the requested speed.
+    //
+    // A failed write leaves the recorded speed untouched so that the next cycle
+    // tries again. Only after max_failures failures in a row the fan is asked
+    // for the fallback speed, which is the safe choice while the bus is flaky.
+    //
+    // The speed the fan already runs at is not written again, except every
+    // refresh_interval seconds in case the fan controller was reset.
+    //
+    // Speeds are quantized to the speed granularity here rather than by the
+    // firmware, which may round them in its own way.
+    async fn set_speed(&mut self, speed: u8) {
+        let speed = quantize_speed(speed, self.speed_granularity);
+        let refresh_interval = Duration::from_secs(self.i2c_settings.refresh_interval);
+        let fresh = self.written_at.is_some_and(|written_at| refresh_interval.is_zero() || written_at.elapsed() < refresh_interval);
+        if speed == self.speed && fresh {
+            return;
+        }
+        if let Some((kick_start_speed, kick_start_duration)) = self.kick_start {
+            if self.speed == 0 && speed > 0 && speed < kick_start_speed && self.write(kick_start_speed).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(kick_start_duration)).await;
+            }
+        }
+        match self.write(speed).await {
+            Ok(()) => {
+                self.record_speed(speed);
+                self.failures = 0;
+                self.written_at = Some(Instant::now());
+            },
+            Err(error) => {
+                self.failures += 1;
+                log::warn!("Fan '{}': failed to set speed to {}% ({} failures in a row): {}", self.name, speed, self.failures, error);
+                if self.failures >= self.i2c_settings.max_failures {
+                    let fallback_speed = self.i2c_settings.fallback_speed;
+                    log::error!("Fan '{}': too many I2C errors, falling back to {}%", self.name, fallback_speed);
+                    self.state.alerts.send(alerts::Alert::I2cErrors, format!("Fan '{}': {} failed writes in a row: {}", self.name, self.failures, error));
+                    if self.write(fallback_speed).await.is_ok() {
+                        self.record_speed(fallback_speed);
+                        self.written_at = Some(Instant::now());
+                    }
+                }
+            },
+        };
+    }
+
+    // Read before the speed changes, so the fan had the whole last cycle to
+    // get up to speed. A fan standing still while it is driven is stalled or
+    // disconnected.
+    fn check_rpm(&mut self, rpm_register: u8) -> Option<u16> {
+        let rpm = match read_fan_rpm(&self.i2c_interface, self.i2c_settings.address, rpm_register) {
+            Ok(rpm) => rpm,
+            Err(error) => {
+                stats::Stats::count(&self.state.stats.i2c_read_failures);
+                log::warn!("Fan '{}': failed to read the RPM: {}", self.name, error);
+                return None;
+            },
+        };
+        let stalled = rpm == 0 && self.speed > 0;
+        if stalled != self.stalled {
+            match stalled {
+                true => {
+                    log::error!("Fan '{}' reports 0 RPM while running at {}%, it is stalled or disconnected", self.name, self.speed);
+                    self.state.alerts.send(alerts::Alert::Stall, format!("Fan '{}' reports 0 RPM while running at {}%", self.name, self.speed));
+                },
+                false => log::info!("Fan '{}' is turning again at {} RPM", self.name, rpm),
+            };
+            self.state.emit(hooks::Event::Stall { fan: &self.name, speed: self.speed, stalled });
+            self.stalled = stalled;
+        }
+        return Some(rpm);
+    }
+}
+
+fn validate_fan(name: &str, fan_config: &FanConfig) -> Result<(), ConfigError> {
+    match fan_config.dynamic {
+        None => { return Err(ConfigError::NoDynamic(String::from(name))); },
+        Some(false) => {
+            if fan_config.const_fan_speed.is_none() {
+                return Err(ConfigError::NoConstantSpeed(String::from(name)));
+            }
+        },
+        Some(true) => {
+            if fan_config.step.as_ref().is_none_or(|step| step.is_empty()) {
+                return Err(ConfigError::EmptyStepConfig(String::from(name)));
+            }
+            if fan_config.min_speed.unwrap_or(u8::MIN) > fan_config.max_speed.unwrap_or(u8::MAX) {
+                return Err(ConfigError::InvalidSpeedRange(String::from(name)));
+            }
         },
     };
+    if fan_config.sources.iter().flatten().any(|source| source.step.as_ref().is_some_and(|step| step.is_empty())) {
+        return Err(ConfigError::EmptyStepConfig(String::from(name)));
+    }
+    if fan_config.speed_granularity.is_some_and(|granularity| granularity == 0 || granularity > 100) {
+        return Err(ConfigError::InvalidGranularity(String::from(name)));
+    }
     return Ok(());
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let gpio_interface = Gpio::new()?;
-    let mut i2c_interface = I2c::new()?;
-    i2c_interface.set_slave_address(FAN_ADDR)?;
-    let shutdown_check_handler = thread::spawn(move || {
-        shutdown_check(gpio_interface, 4).expect("Error monitoring the shutdown button");
+// Profiles only override the fields they set, everything else comes from the
+// configuration of the fan itself.
+fn resolve_profiles(name: &str, fan_config: &FanConfig, profiles: &BTreeMap<String, FanConfig>) -> Result<BTreeMap<String, FanConfig>, ConfigError> {
+    let mut profile_configs = BTreeMap::new();
+    validate_fan(name, fan_config)?;
+    for (profile_name, profile) in profiles.iter() {
+        let profile_config = profile.clone().inherit(fan_config);
+        validate_fan(name, &profile_config).map_err(|error| ConfigError::Profile(profile_name.clone(), Box::new(error)))?;
+        profile_configs.insert(profile_name.clone(), profile_config);
+    }
+    return Ok(profile_configs);
+}
+
+fn select_config<'a>(fan_config: &'a FanConfig, profile_configs: &'a BTreeMap<String, FanConfig>, profile: Option<&str>) -> &'a FanConfig {
+    return match profile {
+        Some(profile_name) => profile_configs.get(profile_name).unwrap_or(fan_config),
+        None => fan_config,
+    };
+}
+
+enum Wake {
+    Timeout,
+    SpeedOverrideChanged,
+    Termination,
+}
+
+struct FanController {
+    name: String,
+    fan: Fan,
+    fan_config: FanConfig,
+    profile_configs: BTreeMap<String, FanConfig>,
+    schedule: Vec<schedule::ScheduleEntry>,
+    sources: Vec<TemperatureSource>,
+    state: SharedState,
+    shutdown_requested: bool,
+    critical: bool,
+    sensor_failures: u32,
+    last_throttled: u32,
+    throttled_unreadable: bool,
+    poll_interval: u64,
+    reference_temperature: Option<f32>,
+    // The buffers of every cycle, the targets of the previous one being kept
+    // for the next.
+    readings: Vec<Option<f32>>,
+    source_targets: Vec<u8>,
+    next_targets: Vec<u8>,
+    // What the settings made of the configuration of a profile, until either
+    // changes.
+    applied_config: Option<(Option<String>, u64, FanConfig)>,
+}
+
+impl FanController {
+    fn active_profile(&self) -> Option<String> {
+        let profile_override = self.state.profile_override.lock().unwrap().clone();
+        return profile_override.or_else(|| schedule::active_profile(&self.schedule).map(String::from));
+    }
+
+    fn apply_settings(&mut self, profile: &Option<String>) {
+        let generation = self.state.settings.generation();
+        if self.applied_config.as_ref().is_some_and(|(applied_profile, applied_generation, _)| applied_profile == profile && *applied_generation == generation) {
+            return;
+        }
+        let config = self.state.settings.apply(&self.name, select_config(&self.fan_config, &self.profile_configs, profile.as_deref())).into_owned();
+        self.applied_config = Some((profile.clone(), generation, config));
+    }
+
+    // Returns whether the daemon is terminating.
+    async fn cycle(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        let profile = self.active_profile();
+        self.apply_settings(&profile);
+        let active_config = &self.applied_config.as_ref().unwrap().2;
+        let delay: u64 = active_config.delay_on_change.unwrap_or(DEFAULT_DELAY_ON_CHANGE);
+        self.fan.speed_granularity = active_config.speed_granularity.unwrap_or(1);
+        let monitored = active_config.dynamic == Some(true)
+            || active_config.critical_temperature.is_some()
+            || active_config.shutdown_temperature.is_some();
+        // The longest this cycle may wait: for the temperature to settle, which
+        // a source may hold up for longer, at the slowest ramp, and until the
+        // next cycle.
+        let decay_duration = self.sources.iter().filter_map(|source| source.delay_on_change).fold(delay, u64::max);
+        let ramp_duration = active_config.ramp_rate.filter(|ramp_rate| *ramp_rate > 0).map_or(0, |ramp_rate| 100 / ramp_rate as u64 + 1);
+        let due_within = Duration::from_secs(decay_duration + ramp_duration + active_config.max_poll_interval.unwrap_or(0).max(delay)) + health::GRACE;
+        self.state.health.cycle(&self.name, due_within, monitored);
+        // A sensor that stops answering must not leave the fan wherever it
+        // was, so after a few failures in a row the fallback speed is used
+        // until readings come back.
+        let readings = match monitored {
+            true => match read_temperatures(&self.name, &self.sources, &self.state, &mut self.readings).await {
+                Ok(()) => {
+                    self.state.health.read(&self.name);
+                    if self.sensor_failures >= active_config.max_sensor_failures.unwrap_or(3) {
+                        log::info!("Fan '{}': temperature readings are back", self.name);
+                    }
+                    self.sensor_failures = 0;
+                    Some(self.readings.as_slice())
+                },
+                Err(error) => {
+                    let max_sensor_failures = active_config.max_sensor_failures.unwrap_or(3);
+                    let fallback_speed = active_config.fallback_speed.unwrap_or(100);
+                    self.sensor_failures += 1;
+                    stats::Stats::count(&self.state.stats.sensor_failures);
+                    log::warn!("Fan '{}': failed to read the temperature ({} failures in a row): {}", self.name, self.sensor_failures, error);
+                    if self.sensor_failures == max_sensor_failures {
+                        log::error!("Fan '{}': no temperature readings, running at the fallback speed of {}%", self.name, fallback_speed);
+                    }
+                    if self.sensor_failures >= max_sensor_failures {
+                        self.fan.set_speed(fallback_speed).await;
+                    }
+                    return match self.state.wait_for_change(Duration::from_secs(SENSOR_RETRY_INTERVAL)).await {
+                        Wake::Termination => Ok(true),
+                        _ => Ok(false),
+                    };
+                },
+            },
+            false => None,
+        };
+        let current_temperature = readings.map(|readings| readings.iter().flatten().copied().fold(f32::MIN, f32::max));
+        let rpm = match active_config.rpm_register {
+            Some(rpm_register) => self.fan.check_rpm(rpm_register),
+            None => None,
+        };
+        if let (Some(temperature), Some(shutdown_temperature)) = (current_temperature, active_config.shutdown_temperature) {
+            if temperature >= shutdown_temperature as f32 && !self.shutdown_requested {
+                log::error!("Fan '{}' reads {}, above the shutdown temperature, powering off", self.name, self.state.units.format(temperature));
+                action::Action::Poweroff.run("temperature", &self.state)?;
+                self.shutdown_requested = true;
+            }
+        }
+        let critical = match (current_temperature, active_config.critical_temperature) {
+            (Some(temperature), Some(critical_temperature)) => temperature >= critical_temperature as f32,
+            _ => false,
+        };
+        source_targets(active_config, &self.sources, readings, critical, self.fan.speed, &mut self.next_targets);
+        let mut target_fan_speed = self.next_targets.iter().copied().max().unwrap_or(0);
+        if let Some(boost_speed) = active_config.throttle_boost_speed {
+            // Without the mailbox nor the hwmon sensor, the fan runs as if
+            // the SoC was never throttled, which is logged only once.
+            let throttled = match read_throttled().await {
+                Ok(throttled) => {
+                    if self.throttled_unreadable {
+                        log::info!("Fan '{}': the throttling state is readable again", self.name);
+                    }
+                    self.throttled_unreadable = false;
+                    throttled
+                },
+                Err(error) => {
+                    if !self.throttled_unreadable {
+                        log::warn!("Fan '{}': failed to read the throttling state, no throttle boost until it can: {}", self.name, error);
+                    }
+                    self.throttled_unreadable = true;
+                    0
+                },
+            };
+            target_fan_speed = apply_throttle_policy(&self.name, throttled, self.last_throttled, boost_speed, target_fan_speed);
+            self.last_throttled = throttled;
+        }
+        // Counted from the start of the daemon, so a fan task restarted by the
+        // supervisor does not boost again.
+        let boot_boost = active_config.boot_boost.and_then(|boot_boost| {
+            let until = self.state.started_at? + Duration::from_secs(boot_boost.duration.unwrap_or(60));
+            return (Instant::now() < until).then_some((boot_boost.speed.unwrap_or(100), until));
+        });
+        if let Some((boost_speed, _)) = boot_boost {
+            target_fan_speed = target_fan_speed.max(boost_speed);
+        }
+        // A manual override wins over everything but the critical temperature.
+        let speed_override = self.state.active_speed_override();
+        if let (false, Some(speed_override)) = (critical, speed_override) {
+            target_fan_speed = speed_override.speed;
+        }
+        if critical != self.critical {
+            if let Some(temperature) = current_temperature {
+                if critical {
+                    log::warn!("Fan '{}' reads {}, above the critical temperature, running at full speed", self.name, self.state.units.format(temperature));
+                    self.state.alerts.send(alerts::Alert::Critical, format!("Fan '{}' reads {}, above the critical temperature", self.name, self.state.units.format(temperature)));
+                }
+                self.state.emit(hooks::Event::Threshold { fan: &self.name, threshold: "critical", temperature: self.state.units.convert_celsius(temperature), above: critical });
+            }
+            self.critical = critical;
+        }
+        // A drop waits for the longest delay of the sources that asked for
+        // more than the new target last time.
+        let decay_delay = self.sources.iter().zip(self.source_targets.iter())
+            .filter(|(_, previous_target)| **previous_target > target_fan_speed)
+            .map(|(source, _)| source.delay_on_change.unwrap_or(delay))
+            .max()
+            .unwrap_or(delay);
+        std::mem::swap(&mut self.source_targets, &mut self.next_targets);
+        if !critical && speed_override.is_none() && target_fan_speed < self.fan.speed {
+            match self.decay(active_config, decay_delay, current_temperature.is_some(), &mut target_fan_speed).await {
+                Wake::Timeout => {},
+                Wake::SpeedOverrideChanged => { return Ok(false); },
+                Wake::Termination => { return Ok(true); },
+            };
+            target_fan_speed = step_down(active_config, &self.sources, self.fan.speed, target_fan_speed);
+        }
+        let ramp_rate = match critical {
+            true => None,
+            false => active_config.ramp_rate.filter(|ramp_rate| *ramp_rate > 0),
+        };
+        match self.fan.ramp_to(target_fan_speed, ramp_rate).await {
+            Wake::Timeout => {},
+            Wake::SpeedOverrideChanged => { return Ok(false); },
+            Wake::Termination => { return Ok(true); },
+        };
+        // While the temperature stays within stable_temperature of where it
+        // settled, the wait between cycles doubles up to max_poll_interval. It
+        // drops back to delay_on_change as soon as the temperature moves.
+        let stable = match (current_temperature, self.reference_temperature) {
+            (Some(temperature), Some(reference)) => (temperature - reference).abs() <= active_config.stable_temperature.unwrap_or(1.0),
+            _ => false,
+        };
+        if !stable {
+            self.reference_temperature = current_temperature;
+        }
+        let fan_state = FanState {
+            temperature: current_temperature,
+            smoothed_temperature: self.reference_temperature,
+            target_speed: target_fan_speed,
+            fan_speed: self.fan.speed,
+            rpm,
+            profile: profile.clone(),
+            critical,
+        };
+        // Updated in place, the name is only copied for the first cycle.
+        let previous = {
+            let mut fans = self.state.fans.lock().unwrap();
+            match fans.get_mut(&self.name) {
+                Some(previous) => Some(std::mem::replace(previous, fan_state)),
+                None => fans.insert(self.name.clone(), fan_state),
+            }
+        };
+        if previous.is_some_and(|previous| previous.profile != profile) {
+            self.state.emit(hooks::Event::Profile { fan: &self.name, profile: profile.as_deref() });
+        }
+        self.state.emit(hooks::Event::Temperature {
+            fan: &self.name,
+            temperature: current_temperature.map(|temperature| self.state.units.convert_celsius(temperature)),
+            target_speed: target_fan_speed,
+            speed: self.fan.speed,
+        });
+        self.poll_interval = match active_config.max_poll_interval {
+            Some(max_poll_interval) if stable => (self.poll_interval * 2).min(max_poll_interval).max(delay),
+            _ => delay,
+        };
+        // With a critical temperature set, the temperature keeps being watched
+        // while waiting so that a runaway workload is caught within seconds.
+        let mut waited = 0;
+        while waited < self.poll_interval {
+            let mut interval = match active_config.critical_temperature {
+                Some(_) => CRITICAL_POLL_INTERVAL.min(self.poll_interval - waited),
+                None => self.poll_interval - waited,
+            };
+            let until = speed_override.and_then(|speed_override| speed_override.until).into_iter()
+                .chain(boot_boost.map(|(_, until)| until))
+                .min();
+            if let Some(until) = until {
+                interval = interval.min(until.saturating_duration_since(Instant::now()).as_millis().div_ceil(1000) as u64);
+            }
+            match self.state.wait_for_change(Duration::from_secs(interval)).await {
+                Wake::Timeout => {},
+                Wake::SpeedOverrideChanged => { break; },
+                Wake::Termination => { return Ok(true); },
+            };
+            waited += interval;
+            if speed_override.is_some() && self.state.active_speed_override().is_none() {
+                break;
+            }
+            if boot_boost.is_some_and(|(_, until)| Instant::now() >= until) {
+                break;
+            }
+            if let Some(critical_temperature) = active_config.critical_temperature {
+                // A failed reading is left to the next cycle to deal with.
+                if !critical && read_max_temperature(&self.sources, &self.state).await.ok().is_none_or(|temperature| temperature >= critical_temperature as f32) {
+                    break;
+                }
+            }
+        }
+        return Ok(false);
+    }
+
+    // Before slowing down, the temperature has to stay low for the whole
+    // delay, checked every few seconds, and the target becomes the highest one
+    // seen meanwhile. A reading back at the current speed or a failed one
+    // keeps the fan where it is.
+    async fn decay(&self, active_config: &FanConfig, delay: u64, monitored: bool, target: &mut u8) -> Wake {
+        let mut waited = 0;
+        while waited < delay && *target < self.fan.speed {
+            let interval = match monitored {
+                true => DECAY_POLL_INTERVAL.min(delay - waited),
+                false => delay - waited,
+            };
+            match self.state.wait_for_change(Duration::from_secs(interval)).await {
+                Wake::Timeout => {},
+                wake => { return wake; },
+            };
+            waited += interval;
+            if monitored {
+                *target = match read_max_target(active_config, &self.sources, &self.state, self.fan.speed).await {
+                    Ok(read_target) => {
+                        self.state.health.read(&self.name);
+                        read_target.max(*target)
+                    },
+                    Err(_) => self.fan.speed,
+                };
+            }
+        }
+        return Wake::Timeout;
+    }
+
+    // Without exit_fan_speed, dynamic fans are stopped and constant ones are
+    // left running as they are.
+    async fn exit(&mut self) {
+        let profile = self.active_profile();
+        let active_config = &self.state.settings.apply(&self.name, select_config(&self.fan_config, &self.profile_configs, profile.as_deref()));
+        let exit_fan_speed = match (active_config.exit_fan_speed, active_config.dynamic) {
+            (Some(ExitFanSpeed::Speed(speed)), _) => Some(speed),
+            (Some(ExitFanSpeed::Hold(_)), _) => None,
+            (None, Some(true)) => Some(0),
+            (None, _) => None,
+        };
+        self.fan.speed_granularity = active_config.speed_granularity.unwrap_or(1);
+        if let Some(speed) = exit_fan_speed {
+            self.fan.set_speed(speed).await;
+        }
+    }
+}
+
+async fn fan_check(
+    name: String,
+    fan_config: FanConfig,
+    profile_configs: BTreeMap<String, FanConfig>,
+    schedule: Vec<schedule::ScheduleEntry>,
+    i2c_interface: Arc<Mutex<I2c>>,
+    i2c_settings: I2cSettings,
+    state: SharedState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut fan = Fan {
+        name: name.clone(),
+        i2c_interface,
+        state: Arc::clone(&state),
+        register: fan_config.register.unwrap_or(i2c_settings.register),
+        speed: 0,
+        kick_start: fan_config.kick_start_speed.map(|speed| (speed, fan_config.kick_start_duration.unwrap_or(500))),
+        speed_granularity: fan_config.speed_granularity.unwrap_or(1),
+        i2c_settings,
+        failures: 0,
+        stalled: false,
+        written_at: None,
+    };
+    // Picking up at the speed of the previous run, or of the previous attempt
+    // after a restart by the supervisor, avoids a glitch until the first cycle.
+    let last_speed = state.fans.lock().unwrap().get(&name).map(|fan_state| fan_state.fan_speed);
+    if let Some(speed) = last_speed {
+        fan.set_speed(speed).await;
+    }
+    let mut controller = FanController {
+        name,
+        fan,
+        sources: fan_config.sources.clone().unwrap_or_else(|| vec![TemperatureSource { kind: SensorKind::Cpu, offset: None, scale: None, step: None, delay_on_change: None }]),
+        fan_config,
+        profile_configs,
+        schedule,
+        state,
+        shutdown_requested: false,
+        critical: false,
+        sensor_failures: 0,
+        last_throttled: 0,
+        throttled_unreadable: false,
+        poll_interval: 0,
+        reference_temperature: None,
+        readings: Vec::new(),
+        source_targets: Vec::new(),
+        next_targets: Vec::new(),
+        applied_config: None,
+    };
+    let result = loop {
+        match controller.cycle().await {
+            Ok(true) => { break Ok(()); },
+            Ok(false) => {},
+            Err(error) => { break Err(error); },
+        };
+    };
+    controller.exit().await;
+    return result;
+}
+
+// Everything needed to tell why a fan runs at the speed it does.
+fn dump_state(state: &State) {
+    log::info!("State dump requested");
+    for (name, fan_state) in state.fans.lock().unwrap().iter() {
+        let temperature = match (fan_state.temperature, fan_state.smoothed_temperature) {
+            (Some(temperature), Some(smoothed)) => format!("{} (smoothed {})", state.units.format(temperature), state.units.format(smoothed)),
+            (Some(temperature), None) => state.units.format(temperature),
+            (None, _) => String::from("not monitored"),
+        };
+        let rpm = match fan_state.rpm {
+            Some(rpm) => format!("{} RPM", rpm),
+            None => String::from("unknown"),
+        };
+        log::info!("Fan '{}': temperature {}, target speed {}%, written speed {}%, {}, profile {}",
+            name, temperature, fan_state.target_speed, fan_state.fan_speed, rpm, fan_state.profile.as_deref().unwrap_or("none"));
+    }
+    log::info!("Profile override: {}", state.profile_override.lock().unwrap().as_deref().unwrap_or("none"));
+    log::info!("Last button pulse: {}ms", state.last_button_pulse.load(Ordering::SeqCst));
+    match *state.speed_override.lock().unwrap() {
+        Some(SpeedOverride { speed, until: Some(until) }) => log::info!("Speed override: {}% for {}s", speed, until.saturating_duration_since(Instant::now()).as_secs()),
+        Some(SpeedOverride { speed, until: None }) => log::info!("Speed override: {}%", speed),
+        None => log::info!("Speed override: none"),
+    };
+    match *state.battery.lock().unwrap() {
+        Some(battery) => log::info!("Battery: {:.2}V, {:.0}%", battery.voltage, battery.charge),
+        None => log::info!("Battery: none"),
+    };
+    for (name, _, count) in state.stats.counters().iter() {
+        log::info!("Errors, {}: {}", name, count);
+    }
+    match state.mcu.as_ref().and_then(|mcu| mcu.firmware) {
+        Some(firmware) => log::info!("Fan controller: {}", firmware),
+        None => log::info!("Fan controller: not detected"),
+    };
+}
+
+// Restarts a failed subsystem with an exponential backoff until the daemon is
+// asked to stop. A subsystem that kept running for longer than the maximum
+// delay is considered healthy again and restarts quickly on its next failure.
+async fn supervise<F, T>(name: String, state: SharedState, mut task: F)
+where
+    F: FnMut() -> T,
+    T: Future<Output = Result<(), Box<dyn std::error::Error>>> + 'static,
+{
+    let mut restart_delay = RESTART_DELAY_MIN;
+    loop {
+        let started = Instant::now();
+        // Every attempt runs as a task of its own so that a panic ends up here
+        // instead of taking the whole daemon down.
+        let error = match tokio::task::spawn_local(task()).await {
+            Ok(Ok(())) => { return; },
+            Ok(Err(error)) => error.to_string(),
+            Err(_) => String::from("panicked"),
+        };
+        if started.elapsed() > Duration::from_secs(RESTART_DELAY_MAX) {
+            restart_delay = RESTART_DELAY_MIN;
+        }
+        log::error!("Error in {}: {}, restarting in {}s", name, error, restart_delay);
+        if state.termination.wait(Duration::from_secs(restart_delay)).await {
+            return;
+        }
+        restart_delay = (restart_delay * 2).min(RESTART_DELAY_MAX);
+    }
+}
+
+// Retries opening a device until it shows up, for when the daemon is started
+// before the i2c-dev module is loaded or the fan controller is ready.
+async fn wait_for_device<T, F>(name: &str, timeout: Duration, mut open: F) -> Result<T, Box<dyn std::error::Error>>
+where
+    F: FnMut() -> Result<T, Box<dyn std::error::Error>>,
+{
+    let started = Instant::now();
+    let mut waited = false;
+    loop {
+        match open() {
+            Ok(device) => {
+                if waited {
+                    log::info!("{} is available after {}s", name, started.elapsed().as_secs());
+                }
+                return Ok(device);
+            },
+            Err(error) => {
+                if started.elapsed() >= timeout {
+                    return Err(error);
+                }
+                if !waited {
+                    log::warn!("{} is not available yet ({}), waiting up to {}s", name, error, timeout.as_secs());
+                }
+            },
+        };
+        waited = true;
+        tokio::time::sleep(DEVICE_RETRY_INTERVAL).await;
+    }
+}
+
+// Spins every fan up, so that a disconnected fan header or a dead fan
+// controller shows up in the log right away. The fan controller cannot be read
+// back, the throttling state is all the firmware has to tell.
+async fn self_test(fans: &BTreeMap<String, FanConfig>, i2c_interface: &Mutex<I2c>, i2c_settings: I2cSettings, duration: Duration) {
+    let mut passed = true;
+    for (name, fan) in fans.iter() {
+        match write_fan_speed(i2c_interface, i2c_settings.address, fan.register.unwrap_or(i2c_settings.register), 100) {
+            Ok(()) => log::info!("Self-test: fan '{}' set to 100%", name),
+            Err(error) => {
+                log::error!("Self-test: failed to set fan '{}' to 100%: {}", name, error);
+                passed = false;
+            },
+        };
+    }
+    tokio::time::sleep(duration).await;
+    match read_throttled().await {
+        Ok(throttled) => log::info!("Self-test: firmware throttling state: {}", describe_throttled(throttled)),
+        Err(error) => log::warn!("Self-test: failed to read the firmware throttling state: {}", error),
+    };
+    match passed {
+        true => log::info!("Self-test passed"),
+        false => log::error!("Self-test failed"),
+    };
+}
+
+// A quick write is how i2cdetect finds devices too, it changes nothing on the
+// fan controller.
+fn probe(i2c_interface: &mut I2c, address: u16) -> Result<(), Box<dyn std::error::Error>> {
+    i2c_interface.set_slave_address(address)?;
+    i2c_interface.smbus_quick_command(false)?;
+    return Ok(());
+}
+
+async fn daemon(config_path: &str, format: Option<ConfigFormat>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = load_config(config_path, format)?;
+    apply_environment(&mut config)?;
+    apply_units(&mut config);
+    let i2c_config = config.i2c.take().unwrap_or_default();
+    let protocol = i2c_config.protocol;
+    let i2c_bus = i2c_config.bus;
+    let mut i2c_settings = I2cSettings::from(i2c_config);
+    let startup_config = config.startup.take().unwrap_or_default();
+    let hardware_config = config.hardware.take().unwrap_or_default();
+    // Held until the daemon returns, a second daemon would fight this one
+    // over the fan and the button.
+    let _lock = match lock::InstanceLock::acquire(startup_config.lock_file.as_deref().unwrap_or(lock::DEFAULT_LOCK)) {
+        Ok(lock) => lock,
+        Err(error) => {
+            log::error!("{}", error);
+            return Err(error);
+        },
+    };
+    let device_timeout = Duration::from_secs(startup_config.timeout.unwrap_or(60));
+    let gpio_interface = wait_for_device("The GPIO", device_timeout, || {
+        return Ok(Gpio::open(&hardware_config)?);
+    }).await?;
+    let mut i2c_interface = wait_for_device("The I2C bus", device_timeout, || {
+        return Ok(I2c::open(&hardware_config, i2c_bus)?);
+    }).await?;
+    // The fan controller not answering is not fatal, the fan writes keep being
+    // retried once the daemon runs.
+    let probed = wait_for_device("The fan controller", device_timeout, || {
+        return probe(&mut i2c_interface, i2c_settings.address);
+    }).await;
+    if let Err(ref error) = probed {
+        log::warn!("The fan controller at {:#x} does not answer: {}", i2c_settings.address, error);
+    }
+    // Detection writes to the fan controller, a configured protocol skips it.
+    let firmware = match (protocol, probed) {
+        (Some(protocol), _) => Some(mcu::Firmware { protocol, version: None }),
+        (None, Ok(())) => Some(mcu::detect(&mut i2c_interface, i2c_settings.address)),
+        (None, Err(_)) => None,
+    };
+    if let Some(firmware) = firmware {
+        log::info!("The fan controller at {:#x} has {}", i2c_settings.address, firmware);
+        i2c_settings.register = firmware.protocol.speed_register();
+    }
+    let i2c_interface = Arc::new(Mutex::new(i2c_interface));
+    // Signals are only caught from here on, until then there is nothing to
+    // clean up and they may as well end the process.
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigquit = signal(SignalKind::quit())?;
+    let mut sigusr1 = signal(SignalKind::user_defined1())?;
+    let state: SharedState = Arc::new(State {
+        hooks: config.hooks.take().unwrap_or_default(),
+        system: config.system.take().and_then(|system| system.init).unwrap_or_default().actions(),
+        inhibit: config.inhibit.take().unwrap_or_default(),
+        alerts: alerts::Alerts::new(config.alerts.take()),
+        cluster: cluster::Cluster::new(config.cluster.as_ref()),
+        mcu: Some(mcu::Mcu::new(Arc::clone(&i2c_interface), i2c_settings.address, firmware)),
+        started_at: Some(Instant::now()),
+        units: config.units.unwrap_or_default(),
+        ..State::default()
     });
-    let fan_check_handler = thread::spawn(move || {
-        return fan_check(i2c_interface).expect("Error keeping the fan running");
+    if let Some(rtc_config) = config.rtc.take() {
+        rtc::rtc_init(rtc_config, &i2c_interface);
+    }
+    let power_config = config.power.take().unwrap_or_default();
+    if let Some(cut_delay) = power_config.cut_delay {
+        match state.mcu.as_ref().unwrap().set_power_cut_delay(cut_delay) {
+            Ok(()) => log::info!("Power cut delay set to {}s", cut_delay),
+            Err(error) => log::warn!("Failed to set the power cut delay: {}", error),
+        };
+    }
+    if let Some(always_on) = power_config.always_on {
+        match state.mcu.as_ref().unwrap().set_always_on(always_on) {
+            Ok(()) => log::info!("Always on mode {}", if always_on { "enabled" } else { "disabled" }),
+            Err(error) => log::warn!("Failed to set the always on mode: {}", error),
+        };
+    }
+    let profiles = config.profile.take().unwrap_or_default();
+    let schedule = config.schedule.take().unwrap_or_default();
+    let control_config = config.control.take().unwrap_or_default();
+    let profile_names: Vec<String> = profiles.keys().cloned().collect();
+    let ir_config = config.ir.take();
+    let display_config = config.display.take();
+    let history_config = config.history.take();
+    let ups_config = config.ups.take();
+    let led_config = config.led.take();
+    let cluster_config = config.cluster.take();
+    let persist_config = config.persist.take().unwrap_or_default();
+    let button_config = config.button.take().unwrap_or_default();
+    #[cfg(feature = "dashboard")]
+    let dashboard_config = config.dashboard.take();
+    let fans = resolve_fans(config);
+    let mut fan_profile_configs = BTreeMap::new();
+    for (name, fan) in fans.iter() {
+        fan_profile_configs.insert(name.clone(), resolve_profiles(name, fan, &profiles)?);
+    }
+    let fan_names: Vec<String> = fans.keys().cloned().collect();
+    let toml_path = match format.unwrap_or_else(|| ConfigFormat::from_path(config_path)) {
+        ConfigFormat::Toml => Some(String::from(config_path)),
+        _ => None,
+    };
+    state.settings.configure(toml_path, &fans);
+    if let Err(error) = persist::restore(&persist_config, &state, &fan_names, &profile_names) {
+        log::warn!("Failed to restore the state of the previous run: {}", error);
+    }
+    if startup_config.self_test.unwrap_or(false) {
+        let duration = Duration::from_secs(startup_config.self_test_duration.unwrap_or(2));
+        self_test(&fans, &i2c_interface, i2c_settings, duration).await;
+    }
+    // The IR receiver and the button only wait for events and have nothing to
+    // clean up, so they are dropped with the runtime when the daemon exits.
+    if let Some(ir_config) = ir_config {
+        let ir_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the IR receiver"), Arc::clone(&state), move || {
+            return ir::ir_check(ir_config.clone(), Arc::clone(&ir_state));
+        }));
+    }
+    let button_state = Arc::clone(&state);
+    tokio::task::spawn_local(supervise(String::from("the shutdown button"), Arc::clone(&state), move || {
+        return shutdown_check(gpio_interface.clone(), button_config.clone(), Arc::clone(&button_state));
+    }));
+    // Every sample is written out right away, so the recorder is not waited
+    // for either.
+    if let Some(history_config) = history_config {
+        let history_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the history recorder"), Arc::clone(&state), move || {
+            return history::history_check(history_config.clone(), Arc::clone(&history_state));
+        }));
+    }
+    if let Some(ups_config) = ups_config {
+        let i2c_interface = Arc::clone(&i2c_interface);
+        let ups_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the UPS"), Arc::clone(&state), move || {
+            return ups::ups_check(ups_config.clone(), Arc::clone(&i2c_interface), Arc::clone(&ups_state));
+        }));
+    }
+    // The LED is left as it is when the daemon exits, breathing if the Pi is
+    // shutting down.
+    if let Some(led_config) = led_config {
+        let led_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the LED"), Arc::clone(&state), move || {
+            return led::led_check(led_config.clone(), Arc::clone(&led_state));
+        }));
+    }
+    if let Some(cluster_config) = cluster_config {
+        let cluster_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the cluster"), Arc::clone(&state), move || {
+            return cluster::cluster_check(cluster_config.clone(), Arc::clone(&cluster_state));
+        }));
+    }
+    let display_check_handler = display_config.map(|display_config| {
+        let i2c_interface = Arc::clone(&i2c_interface);
+        let state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the display"), Arc::clone(&state), move || {
+            return display::display_check(display_config.clone(), Arc::clone(&i2c_interface), Arc::clone(&state));
+        }))
     });
-    shutdown_check_handler.join().unwrap();
-    fan_check_handler.join().unwrap();
+    #[cfg(feature = "dashboard")]
+    if let Some(dashboard_config) = dashboard_config {
+        let profile_names = profile_names.clone();
+        let dashboard_state = Arc::clone(&state);
+        tokio::task::spawn_local(supervise(String::from("the dashboard"), Arc::clone(&state), move || {
+            return dashboard::dashboard_check(dashboard_config.clone(), profile_names.clone(), Arc::clone(&dashboard_state));
+        }));
+    }
+    let control_state = Arc::clone(&state);
+    let control_check_handler = tokio::task::spawn_local(supervise(String::from("the control socket"), Arc::clone(&state), move || {
+        return control::control_check(control_config.clone(), profile_names.clone(), Arc::clone(&control_state));
+    }));
+    let fan_check_handlers: Vec<tokio::task::JoinHandle<()>> = fans.into_iter().map(|(name, fan)| {
+        let i2c_interface = Arc::clone(&i2c_interface);
+        let state = Arc::clone(&state);
+        let profile_configs = fan_profile_configs.remove(&name).unwrap();
+        let schedule = schedule.clone();
+        tokio::task::spawn_local(supervise(format!("fan '{}'", name), Arc::clone(&state), move || {
+            return fan_check(
+                name.clone(),
+                fan.clone(),
+                profile_configs.clone(),
+                schedule.clone(),
+                Arc::clone(&i2c_interface),
+                i2c_settings,
+                Arc::clone(&state),
+            );
+        }))
+    }).collect();
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => { break; },
+            _ = sigint.recv() => { break; },
+            _ = sigquit.recv() => { break; },
+            _ = sigusr1.recv() => dump_state(&state),
+        };
+    }
+    // The fans are stopped first so that their exit speed is written even if
+    // anything else fails to shut down, then the display is turned off and
+    // the control socket goes last.
+    state.termination.request();
+    for fan_check_handler in fan_check_handlers {
+        fan_check_handler.await?;
+    }
+    if let Err(error) = persist::save(&persist_config, &state) {
+        log::warn!("Failed to save the state: {}", error);
+    }
+    if let Some(display_check_handler) = display_check_handler {
+        display_check_handler.await?;
+    }
+    control_check_handler.await?;
+    return Ok(());
+}
+
+// The same sequence as the daemon follows for the button, for shutdowns from
+// a shell. A reboot leaves the power on, so only the init system is involved.
+fn shutdown_command(config_path: &str, format: Option<ConfigFormat>, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = load_config(config_path, format)?;
+    apply_environment(&mut config)?;
+    let actions = config.system.take().and_then(|system| system.init).unwrap_or_default().actions();
+    if command == "poweroff" {
+        let i2c_config = config.i2c.take().unwrap_or_default();
+        let address = i2c_config.address.unwrap_or(FAN_ADDR);
+        let mut i2c_interface = I2c::open(&config.hardware.unwrap_or_default(), i2c_config.bus)?;
+        let firmware = match i2c_config.protocol {
+            Some(protocol) => mcu::Firmware { protocol, version: None },
+            None => mcu::detect(&mut i2c_interface, address),
+        };
+        mcu::Mcu::new(Arc::new(Mutex::new(i2c_interface)), address, Some(firmware)).signal_poweroff()?;
+        return actions.poweroff();
+    }
+    return actions.reboot();
+}
+
+#[derive(Serialize)]
+struct CheckResult {
+    valid: bool,
+    fans: Vec<String>,
+    profiles: Vec<String>,
+    error: Option<String>,
+}
+
+fn check_config(config_path: &str, format: Option<ConfigFormat>) -> Result<(Vec<String>, Vec<String>), Box<dyn std::error::Error>> {
+    let mut config = load_config(config_path, format)?;
+    apply_environment(&mut config)?;
+    apply_units(&mut config);
+    let profiles = config.profile.take().unwrap_or_default();
+    let fans = resolve_fans(config);
+    for (name, fan) in fans.iter() {
+        resolve_profiles(name, fan, &profiles)?;
+    }
+    return Ok((fans.into_keys().collect(), profiles.into_keys().collect()));
+}
+
+// Exits with 1 if the daemon would refuse the configuration.
+fn check_command(config_path: &str, format: Option<ConfigFormat>, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let result = match check_config(config_path, format) {
+        Ok((fans, profiles)) => CheckResult { valid: true, fans, profiles, error: None },
+        Err(error) => CheckResult { valid: false, fans: Vec::new(), profiles: Vec::new(), error: Some(error.to_string()) },
+    };
+    match (json, &result.error) {
+        (true, _) => println!("{}", serde_json::to_string(&result)?),
+        (false, Some(error)) => eprintln!("Error: {}", error),
+        (false, None) => println!("{} is valid: fans {}, profiles {}", config_path,
+            result.fans.join(", "), if result.profiles.is_empty() { String::from("none") } else { result.profiles.join(", ") }),
+    };
+    if !result.valid {
+        std::process::exit(1);
+    }
     return Ok(());
 }
+
+// Every subsystem is a task on one single-threaded runtime, so the tasks need
+// not be Send and shut down in a well-defined order.
+fn run_daemon(config_path: &str, format: Option<ConfigFormat>) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    return tokio::task::LocalSet::new().block_on(&runtime, daemon(config_path, format));
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let filter = std::env::var("ARGONONED_LOG_LEVEL")
+        .or_else(|_| std::env::var("RUST_LOG"))
+        .unwrap_or_else(|_| String::from("info"));
+    env_logger::Builder::new().parse_filters(&filter).init();
+    let cli = Cli::parse();
+    return match cli.command {
+        None | Some(Commands::Daemon) => run_daemon(&cli.config, cli.format),
+        Some(Commands::Rtc { command }) => {
+            let mut config = load_config(&cli.config, cli.format)?;
+            apply_environment(&mut config)?;
+            let i2c_bus = config.i2c.and_then(|i2c_config| i2c_config.bus);
+            rtc::rtc_command(command, I2c::open(&config.hardware.unwrap_or_default(), i2c_bus)?)
+        },
+        Some(Commands::InstallService) => service::install_service(&cli.config, cli.format.map(ConfigFormat::name)),
+        Some(Commands::UninstallService) => service::uninstall_service(),
+        Some(Commands::Poweroff) => shutdown_command(&cli.config, cli.format, "poweroff"),
+        Some(Commands::Reboot) => shutdown_command(&cli.config, cli.format, "reboot"),
+        Some(Commands::Curve { json }) => curve::curve_command(&cli.config, cli.format, json),
+        Some(Commands::Check { json }) => check_command(&cli.config, cli.format, json),
+        Some(Commands::Completions { shell }) => generate::completions(Cli::command(), shell),
+        Some(Commands::Manpage) => generate::manpage(Cli::command()),
+        Some(Commands::Health) => {
+            let mut config = load_config(&cli.config, cli.format)?;
+            apply_environment(&mut config)?;
+            health::health_command(&config.control.unwrap_or_default().socket())
+        },
+        Some(Commands::ImportConfig { input, output }) => import::import_command(&input, output.as_deref()),
+    };
+}
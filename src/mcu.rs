@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+use crate::hardware::I2c;
+
+// Newer firmware (shipped with the Argon ONE V3) exposes registers, older
+// firmware takes the speed as the only byte of a write.
+const DUTY_CYCLE: u8 = 0x80;
+const FIRMWARE_VERSION: u8 = 0x81;
+const POWER_CUT_DELAY: u8 = 0x87;
+const ALWAYS_ON: u8 = 0x88;
+const CONTROL: u8 = 0x86;
+const CONTROL_POWEROFF: u8 = 0x01;
+const RAW_POWEROFF: u8 = 0xff;
+const LED_BRIGHTNESS: u8 = 0x89;
+const LED_MODE: u8 = 0x8a;
+const RAW_REGISTER: u8 = 0x00;
+
+#[derive(Deserialize, Clone, Default)]
+pub struct PowerConfig {
+    pub cut_delay: Option<u8>,
+    pub always_on: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum McuError {
+    Unsupported(&'static str),
+}
+
+impl std::error::Error for McuError {}
+
+impl std::fmt::Display for McuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            McuError::Unsupported(feature) => write!(f, "The firmware of the fan controller does not support the {}", feature),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Raw,
+    Registers,
+}
+
+impl Protocol {
+    // Where the fan speed goes unless a fan sets its own register.
+    pub fn speed_register(self) -> u8 {
+        return match self {
+            Protocol::Raw => RAW_REGISTER,
+            Protocol::Registers => DUTY_CYCLE,
+        };
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Firmware {
+    pub protocol: Protocol,
+    pub version: Option<u8>,
+}
+
+impl std::fmt::Display for Firmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.protocol, self.version) {
+            (Protocol::Registers, Some(version)) => write!(f, "firmware version {}, register protocol", version),
+            (Protocol::Registers, None) => write!(f, "unknown firmware version, register protocol"),
+            (Protocol::Raw, _) => write!(f, "legacy firmware, raw byte protocol"),
+        }
+    }
+}
+
+// Old firmware acknowledges every read with garbage, so the duty cycle
+// register is only trusted if a write to it reads back. This is the check the
+// vendor scripts do, the fan briefly runs one percent faster.
+fn supports_registers(i2c_interface: &mut I2c) -> Result<bool, Box<dyn std::error::Error>> {
+    let speed = i2c_interface.smbus_read_byte(DUTY_CYCLE)?;
+    if speed > 100 {
+        return Ok(false);
+    }
+    let probe = if speed < 100 { speed + 1 } else { speed - 1 };
+    i2c_interface.smbus_write_byte(DUTY_CYCLE, probe)?;
+    let supported = i2c_interface.smbus_read_byte(DUTY_CYCLE)? == probe;
+    i2c_interface.smbus_write_byte(DUTY_CYCLE, speed)?;
+    return Ok(supported);
+}
+
+// Anything that does not answer like the register protocol is treated as the
+// legacy one, which is what the daemon always spoke.
+pub fn detect(i2c_interface: &mut I2c, address: u16) -> Firmware {
+    let supported = i2c_interface.set_slave_address(address).map_err(Box::from).and_then(|()| supports_registers(i2c_interface));
+    return match supported {
+        Ok(true) => Firmware {
+            protocol: Protocol::Registers,
+            version: i2c_interface.smbus_read_byte(FIRMWARE_VERSION).ok(),
+        },
+        Ok(false) | Err(_) => Firmware { protocol: Protocol::Raw, version: None },
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LedMode {
+    Steady = 0,
+    Blink = 1,
+    Breathe = 2,
+}
+
+// The fan controller itself, for the settings besides the fan speed.
+pub struct Mcu {
+    i2c_interface: Arc<Mutex<I2c>>,
+    address: u16,
+    pub firmware: Option<Firmware>,
+}
+
+impl Mcu {
+    pub fn new(i2c_interface: Arc<Mutex<I2c>>, address: u16, firmware: Option<Firmware>) -> Mcu {
+        return Mcu { i2c_interface, address, firmware };
+    }
+
+    pub fn has_registers(&self) -> bool {
+        return self.firmware.is_some_and(|firmware| firmware.protocol == Protocol::Registers);
+    }
+
+    fn registers(&self, feature: &'static str) -> Result<std::sync::MutexGuard<'_, I2c>, Box<dyn std::error::Error>> {
+        if !self.has_registers() {
+            return Err(Box::new(McuError::Unsupported(feature)));
+        }
+        let mut i2c_interface = self.i2c_interface.lock().unwrap();
+        i2c_interface.set_slave_address(self.address)?;
+        return Ok(i2c_interface);
+    }
+
+    // Seconds the power stays on once the OS has shut down, so that a slow
+    // shutdown can finish writing to the disks.
+    pub fn set_power_cut_delay(&self, seconds: u8) -> Result<(), Box<dyn std::error::Error>> {
+        self.registers("power cut delay")?.smbus_write_byte(POWER_CUT_DELAY, seconds)?;
+        return Ok(());
+    }
+
+    // Whether the Pi is powered on as soon as power comes back, instead of
+    // waiting for the power button.
+    pub fn always_on(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        return Ok(self.registers("always on mode")?.smbus_read_byte(ALWAYS_ON)? != 0);
+    }
+
+    pub fn set_always_on(&self, always_on: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.registers("always on mode")?.smbus_write_byte(ALWAYS_ON, always_on as u8)?;
+        return Ok(());
+    }
+
+    // Brightness in percent, of the power button LED of the Argon ONE V3 or the
+    // front LED of the Argon EON.
+    pub fn set_led(&self, mode: LedMode, brightness: u8) -> Result<(), Box<dyn std::error::Error>> {
+        let i2c_interface = self.registers("LED control")?;
+        i2c_interface.smbus_write_byte(LED_BRIGHTNESS, brightness)?;
+        i2c_interface.smbus_write_byte(LED_MODE, mode as u8)?;
+        return Ok(());
+    }
+
+    // Asks the fan controller to cut the power once the power cut delay is
+    // over, by which time the OS has halted. Without it the board keeps
+    // drawing power after a shutdown that did not come from the button.
+    pub fn signal_poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut i2c_interface = self.i2c_interface.lock().unwrap();
+        i2c_interface.set_slave_address(self.address)?;
+        match self.firmware.map(|firmware| firmware.protocol) {
+            Some(Protocol::Registers) => { i2c_interface.smbus_write_byte(CONTROL, CONTROL_POWEROFF)?; },
+            _ => { i2c_interface.write(&[RAW_POWEROFF])?; },
+        };
+        return Ok(());
+    }
+}
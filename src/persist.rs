@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{FanState, SpeedOverride, State};
+
+const DEFAULT_PATH: &str = "/run/argononed.state";
+
+#[derive(Deserialize, Clone, Default)]
+pub struct PersistConfig {
+    path: Option<String>,
+}
+
+impl PersistConfig {
+    fn path(&self) -> &str {
+        return self.path.as_deref().unwrap_or(DEFAULT_PATH);
+    }
+}
+
+// Instants do not survive a restart, so the end of a speed override is kept
+// as a unix timestamp.
+#[derive(Serialize, Deserialize)]
+struct SavedSpeedOverride {
+    speed: u8,
+    until: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Snapshot {
+    fans: BTreeMap<String, u8>,
+    profile_override: Option<String>,
+    speed_override: Option<SavedSpeedOverride>,
+}
+
+fn unix_time() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+}
+
+pub fn save(config: &PersistConfig, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let now = Instant::now();
+    let snapshot = Snapshot {
+        fans: state.fans.lock().unwrap().iter().map(|(name, fan_state)| (name.clone(), fan_state.fan_speed)).collect(),
+        profile_override: state.profile_override.lock().unwrap().clone(),
+        speed_override: state.active_speed_override().map(|speed_override| SavedSpeedOverride {
+            speed: speed_override.speed,
+            until: speed_override.until.map(|until| unix_time() + until.saturating_duration_since(now).as_secs()),
+        }),
+    };
+    std::fs::write(config.path(), serde_json::to_string(&snapshot)?)?;
+    return Ok(());
+}
+
+// Whatever no longer matches the configuration, a fan or a profile that was
+// removed or an override that ran out meanwhile, is dropped.
+pub fn restore(config: &PersistConfig, state: &State, fans: &[String], profiles: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = match std::fs::read_to_string(config.path()) {
+        Ok(contents) => contents,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => { return Ok(()); },
+        Err(error) => { return Err(Box::new(error)); },
+    };
+    let snapshot: Snapshot = serde_json::from_str(&contents)?;
+    let mut fan_states = state.fans.lock().unwrap();
+    for (name, speed) in snapshot.fans.into_iter().filter(|(name, _)| fans.contains(name)) {
+        fan_states.insert(name, FanState { fan_speed: speed, ..FanState::default() });
+    }
+    *state.profile_override.lock().unwrap() = snapshot.profile_override.filter(|profile| profiles.contains(profile));
+    let now = unix_time();
+    *state.speed_override.lock().unwrap() = match snapshot.speed_override {
+        Some(SavedSpeedOverride { speed, until: None }) => Some(SpeedOverride { speed, until: None }),
+        Some(SavedSpeedOverride { speed, until: Some(until) }) if until > now => Some(SpeedOverride {
+            speed,
+            until: Some(Instant::now() + Duration::from_secs(until - now)),
+        }),
+        _ => None,
+    };
+    log::info!("Restored the state of the previous run from {}", config.path());
+    return Ok(());
+}
@@ -0,0 +1,217 @@
+use clap::Subcommand;
+use serde::Deserialize;
+use std::sync::Mutex;
+
+use crate::hardware::I2c;
+use crate::schedule::parse_time_of_day;
+
+const RTC_ADDR: u16 = 0x51;
+
+const CONTROL_STATUS_2: u8 = 0x01;
+const VL_SECONDS: u8 = 0x02;
+const MINUTE_ALARM: u8 = 0x09;
+
+const CONTROL_STATUS_2_AIE: u8 = 0x02;
+const VL_SECONDS_VL: u8 = 0x80;
+const ALARM_DISABLE: u8 = 0x80;
+
+#[derive(Deserialize, Clone)]
+pub struct RtcConfig {
+    sync_at_boot: Option<bool>,
+    wake_alarm: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum RtcCommand {
+    /// Show the time and the wake-up alarm stored in the RTC
+    Show,
+    /// Set the RTC from the system clock
+    SetClock,
+    /// Set the system clock from the RTC
+    Sync,
+    /// Wake the system up every day at the given local time (HH:MM)
+    Alarm { time: String },
+    /// Disable the wake-up alarm
+    ClearAlarm,
+}
+
+#[derive(Debug)]
+pub enum RtcError {
+    ClockIntegrity,
+}
+
+impl std::error::Error for RtcError {}
+
+impl std::fmt::Display for RtcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RtcError::ClockIntegrity => write!(f, "The RTC lost power, its time is not reliable"),
+        }
+    }
+}
+
+fn to_bcd(value: u8) -> u8 {
+    return ((value / 10) << 4) | (value % 10);
+}
+
+fn from_bcd(value: u8) -> u8 {
+    return (value >> 4) * 10 + (value & 0x0f);
+}
+
+fn read_registers(i2c_interface: &Mutex<I2c>, register: u8, buffer: &mut [u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut i2c_interface = i2c_interface.lock().unwrap();
+    i2c_interface.set_slave_address(RTC_ADDR)?;
+    i2c_interface.write_read(&[register], buffer)?;
+    return Ok(());
+}
+
+fn write_registers(i2c_interface: &Mutex<I2c>, register: u8, values: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut i2c_interface = i2c_interface.lock().unwrap();
+    i2c_interface.set_slave_address(RTC_ADDR)?;
+    let mut buffer = vec![register];
+    buffer.extend_from_slice(values);
+    i2c_interface.write(&buffer)?;
+    return Ok(());
+}
+
+// The RTC keeps UTC, so it is unaffected by time zone and DST changes.
+fn read_clock(i2c_interface: &Mutex<I2c>) -> Result<libc::time_t, Box<dyn std::error::Error>> {
+    let mut registers = [0u8; 7];
+    read_registers(i2c_interface, VL_SECONDS, &mut registers)?;
+    if registers[0] & VL_SECONDS_VL != 0 {
+        return Err(Box::new(RtcError::ClockIntegrity));
+    }
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_sec = from_bcd(registers[0] & 0x7f) as libc::c_int;
+    tm.tm_min = from_bcd(registers[1] & 0x7f) as libc::c_int;
+    tm.tm_hour = from_bcd(registers[2] & 0x3f) as libc::c_int;
+    tm.tm_mday = from_bcd(registers[3] & 0x3f) as libc::c_int;
+    tm.tm_mon = from_bcd(registers[5] & 0x1f) as libc::c_int - 1;
+    tm.tm_year = from_bcd(registers[6]) as libc::c_int + 100;
+    return Ok(unsafe { libc::timegm(&mut tm) });
+}
+
+fn write_clock(i2c_interface: &Mutex<I2c>, time: libc::time_t) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::gmtime_r(&time, &mut tm) };
+    return write_registers(i2c_interface, VL_SECONDS, &[
+        to_bcd(tm.tm_sec as u8),
+        to_bcd(tm.tm_min as u8),
+        to_bcd(tm.tm_hour as u8),
+        to_bcd(tm.tm_mday as u8),
+        tm.tm_wday as u8,
+        to_bcd(tm.tm_mon as u8 + 1),
+        to_bcd((tm.tm_year - 100) as u8),
+    ]);
+}
+
+fn system_time() -> libc::time_t {
+    return unsafe { libc::time(std::ptr::null_mut()) };
+}
+
+fn set_system_time(time: libc::time_t) -> Result<(), Box<dyn std::error::Error>> {
+    let timespec = libc::timespec { tv_sec: time, tv_nsec: 0 };
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &timespec) } < 0 {
+        return Err(Box::new(std::io::Error::last_os_error()));
+    }
+    return Ok(());
+}
+
+fn format_time(time: libc::time_t) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    return format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec);
+}
+
+// Alarms are given in local time but matched by the RTC against UTC, so the
+// current UTC offset is applied when the alarm is programmed.
+fn set_alarm(i2c_interface: &Mutex<I2c>, hour: u8, minute: u8) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&system_time(), &mut tm) };
+    let local_minutes = hour as i64 * 60 + minute as i64;
+    let utc_minutes = (local_minutes - tm.tm_gmtoff as i64 / 60).rem_euclid(24 * 60);
+    write_registers(i2c_interface, MINUTE_ALARM, &[
+        to_bcd((utc_minutes % 60) as u8),
+        to_bcd((utc_minutes / 60) as u8),
+        ALARM_DISABLE,
+        ALARM_DISABLE,
+    ])?;
+    return write_registers(i2c_interface, CONTROL_STATUS_2, &[CONTROL_STATUS_2_AIE]);
+}
+
+fn clear_alarm(i2c_interface: &Mutex<I2c>) -> Result<(), Box<dyn std::error::Error>> {
+    write_registers(i2c_interface, MINUTE_ALARM, &[ALARM_DISABLE; 4])?;
+    return write_registers(i2c_interface, CONTROL_STATUS_2, &[0x00]);
+}
+
+fn read_alarm(i2c_interface: &Mutex<I2c>) -> Result<Option<libc::time_t>, Box<dyn std::error::Error>> {
+    let mut control = [0u8; 1];
+    read_registers(i2c_interface, CONTROL_STATUS_2, &mut control)?;
+    let mut alarm = [0u8; 2];
+    read_registers(i2c_interface, MINUTE_ALARM, &mut alarm)?;
+    if control[0] & CONTROL_STATUS_2_AIE == 0 || alarm[0] & ALARM_DISABLE != 0 || alarm[1] & ALARM_DISABLE != 0 {
+        return Ok(None);
+    }
+    let now = system_time();
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::gmtime_r(&now, &mut tm) };
+    tm.tm_sec = 0;
+    tm.tm_min = from_bcd(alarm[0] & 0x7f) as libc::c_int;
+    tm.tm_hour = from_bcd(alarm[1] & 0x3f) as libc::c_int;
+    let mut next = unsafe { libc::timegm(&mut tm) };
+    if next <= now {
+        next += 24 * 60 * 60;
+    }
+    return Ok(Some(next));
+}
+
+pub fn rtc_command(command: RtcCommand, i2c_interface: I2c) -> Result<(), Box<dyn std::error::Error>> {
+    let i2c_interface = Mutex::new(i2c_interface);
+    match command {
+        RtcCommand::Show => {
+            println!("Clock: {}", format_time(read_clock(&i2c_interface)?));
+            match read_alarm(&i2c_interface)? {
+                Some(alarm) => println!("Alarm: {}", format_time(alarm)),
+                None => println!("Alarm: disabled"),
+            };
+        },
+        RtcCommand::SetClock => { write_clock(&i2c_interface, system_time())?; },
+        RtcCommand::Sync => { set_system_time(read_clock(&i2c_interface)?)?; },
+        RtcCommand::Alarm { time } => {
+            let (hour, minute) = parse_time_of_day(&time)?;
+            set_alarm(&i2c_interface, hour, minute)?;
+        },
+        RtcCommand::ClearAlarm => { clear_alarm(&i2c_interface)?; },
+    };
+    return Ok(());
+}
+
+fn sync_clock(i2c_interface: &Mutex<I2c>) -> Result<(), Box<dyn std::error::Error>> {
+    let time = read_clock(i2c_interface)?;
+    set_system_time(time)?;
+    log::info!("System clock set from the RTC to {}", format_time(time));
+    return Ok(());
+}
+
+fn program_alarm(i2c_interface: &Mutex<I2c>, time: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (hour, minute) = parse_time_of_day(time)?;
+    set_alarm(i2c_interface, hour, minute)?;
+    log::info!("Wake-up alarm set to {}", time);
+    return Ok(());
+}
+
+// Like the power settings, a missing RTC or one that lost its time does not
+// keep the daemon from driving the fan.
+pub fn rtc_init(config: RtcConfig, i2c_interface: &Mutex<I2c>) {
+    if config.sync_at_boot.unwrap_or(false) {
+        if let Err(error) = sync_clock(i2c_interface) {
+            log::warn!("Failed to set the system clock from the RTC: {}", error);
+        }
+    }
+    if let Some(time) = config.wake_alarm {
+        if let Err(error) = program_alarm(i2c_interface, &time) {
+            log::warn!("Failed to set the wake-up alarm: {}", error);
+        }
+    };
+}
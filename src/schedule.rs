@@ -0,0 +1,112 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Clone)]
+pub struct ScheduleEntry {
+    from: String,
+    to: String,
+    profile: String,
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    InvalidTime(String),
+    UnknownProfile(String),
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScheduleError::InvalidTime(time) => write!(f, "Invalid time of day '{}', expected HH:MM", time),
+            ScheduleError::UnknownProfile(profile) => write!(f, "Schedule refers to unknown profile '{}'", profile),
+        }
+    }
+}
+
+pub fn parse_time_of_day(time: &str) -> Result<(u8, u8), ScheduleError> {
+    let invalid = || ScheduleError::InvalidTime(String::from(time));
+    let mut parts = time.splitn(2, ':');
+    let hour = parts.next().and_then(|hour| hour.parse::<u8>().ok()).ok_or_else(invalid)?;
+    let minute = parts.next().and_then(|minute| minute.parse::<u8>().ok()).ok_or_else(invalid)?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    return Ok((hour, minute));
+}
+
+fn minutes_of_day(time: &str) -> Result<u16, ScheduleError> {
+    let (hour, minute) = parse_time_of_day(time)?;
+    return Ok(hour as u16 * 60 + minute as u16);
+}
+
+fn local_minutes_of_day() -> u16 {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    return (tm.tm_hour * 60 + tm.tm_min) as u16;
+}
+
+pub fn validate(schedule: &[ScheduleEntry], profiles: Option<&BTreeMap<String, crate::FanConfig>>) -> Result<(), ScheduleError> {
+    for entry in schedule.iter() {
+        minutes_of_day(&entry.from)?;
+        minutes_of_day(&entry.to)?;
+        if !profiles.is_some_and(|profiles| profiles.contains_key(&entry.profile)) {
+            return Err(ScheduleError::UnknownProfile(entry.profile.clone()));
+        }
+    }
+    return Ok(());
+}
+
+// A window whose end is before its start wraps around midnight, like 22:00 to
+// 07:00.
+pub fn in_window(from: &str, to: &str) -> Result<bool, ScheduleError> {
+    return in_window_at(from, to, local_minutes_of_day());
+}
+
+fn in_window_at(from: &str, to: &str, now: u16) -> Result<bool, ScheduleError> {
+    let (from, to) = (minutes_of_day(from)?, minutes_of_day(to)?);
+    return Ok(match from <= to {
+        true => from <= now && now < to,
+        false => now >= from || now < to,
+    });
+}
+
+// The first entry covering the current local time wins.
+pub fn active_profile(schedule: &[ScheduleEntry]) -> Option<&str> {
+    for entry in schedule.iter() {
+        if in_window(&entry.from, &entry.to).unwrap_or(false) {
+            return Some(&entry.profile);
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_within_a_day() {
+        assert!(in_window_at("08:00", "17:30", 8 * 60).unwrap());
+        assert!(in_window_at("08:00", "17:30", 17 * 60 + 29).unwrap());
+        assert!(!in_window_at("08:00", "17:30", 17 * 60 + 30).unwrap());
+        assert!(!in_window_at("08:00", "17:30", 7 * 60 + 59).unwrap());
+    }
+
+    #[test]
+    fn window_around_midnight() {
+        assert!(in_window_at("22:00", "07:00", 23 * 60).unwrap());
+        assert!(in_window_at("22:00", "07:00", 0).unwrap());
+        assert!(in_window_at("22:00", "07:00", 6 * 60 + 59).unwrap());
+        assert!(!in_window_at("22:00", "07:00", 7 * 60).unwrap());
+        assert!(!in_window_at("22:00", "07:00", 12 * 60).unwrap());
+    }
+
+    #[test]
+    fn invalid_window() {
+        assert!(in_window_at("24:00", "07:00", 0).is_err());
+        assert!(in_window_at("22:00", "7", 0).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use std::process::{Command, ExitStatus};
+
+const UNIT_NAME: &str = "argononed.service";
+const UNIT_PATH: &str = "/etc/systemd/system/argononed.service";
+
+#[derive(Debug)]
+pub enum ServiceError {
+    Systemctl(String, ExitStatus),
+}
+
+impl std::error::Error for ServiceError {}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ServiceError::Systemctl(command, status) => write!(f, "systemctl {} failed with {}", command, status),
+        }
+    }
+}
+
+pub fn systemctl(arguments: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("systemctl").args(arguments).status()?;
+    if !status.success() {
+        return Err(Box::new(ServiceError::Systemctl(arguments.join(" "), status)));
+    }
+    return Ok(());
+}
+
+// The i2c-dev module is loaded by systemd-modules-load, so the daemon is
+// ordered after it rather than racing it for /dev/i2c-1. It is not ordered
+// after multi-user.target, which wants it and would make that a cycle.
+fn unit(executable: &str, config_path: &str, format: Option<&str>) -> String {
+    return format!("\
+[Unit]
+Description=Argon ONE fan and power button daemon
+Wants=systemd-modules-load.service
+After=systemd-modules-load.service
+
+[Service]
+Type=simple
+ExecStart=\"{}\" --config \"{}\"{} daemon
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=multi-user.target
+", executable, config_path, format.map(|format| format!(" --format {}", format)).unwrap_or_default());
+}
+
+pub fn install_service(config_path: &str, format: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let executable = std::env::current_exe()?;
+    let config_path = std::fs::canonicalize(config_path).or_else(|_| std::env::current_dir().map(|directory| directory.join(config_path)))?;
+    std::fs::write(UNIT_PATH, unit(&executable.to_string_lossy(), &config_path.to_string_lossy(), format))?;
+    println!("Wrote {}", UNIT_PATH);
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", UNIT_NAME])?;
+    println!("Enabled and started {}", UNIT_NAME);
+    return Ok(());
+}
+
+pub fn uninstall_service() -> Result<(), Box<dyn std::error::Error>> {
+    systemctl(&["disable", "--now", UNIT_NAME])?;
+    std::fs::remove_file(UNIT_PATH)?;
+    println!("Removed {}", UNIT_PATH);
+    systemctl(&["daemon-reload"])?;
+    return Ok(());
+}
@@ -0,0 +1,287 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Mutex;
+
+use crate::{validate_fan, Config, FanConfig, Units};
+
+// The settings every cycle reads afresh, the others only apply when the
+// daemon starts.
+const SETTINGS: [&str; 18] = [
+    "dynamic", "const_fan_speed", "step", "delay_on_change", "rpm_register", "min_speed", "max_speed",
+    "critical_temperature", "shutdown_temperature", "throttle_boost_speed", "exit_fan_speed", "ramp_rate",
+    "max_sensor_failures", "fallback_speed", "max_poll_interval", "stable_temperature", "hysteresis",
+    "speed_granularity",
+];
+
+#[derive(Debug)]
+pub enum SettingError {
+    Unknown(String),
+    UnknownFan(String),
+    Invalid(String, String),
+    NotToml,
+    Save(String, String),
+}
+
+impl std::error::Error for SettingError {}
+
+impl std::fmt::Display for SettingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SettingError::Unknown(key) => write!(f, "Unknown setting '{}', expected one of {}", key, SETTINGS.join(", ")),
+            SettingError::UnknownFan(fan) => write!(f, "Unknown fan '{}'", fan),
+            SettingError::Invalid(key, error) => write!(f, "Invalid value for {}: {}", key, error),
+            SettingError::NotToml => write!(f, "Only a TOML configuration file can be saved to"),
+            SettingError::Save(path, error) => write!(f, "Failed to save to {}: {}", path, error),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    path: Option<String>,
+    fans: BTreeMap<String, FanConfig>,
+    // Set for every fan under None, for a single one under its name.
+    overrides: BTreeMap<Option<String>, FanConfig>,
+    values: BTreeMap<String, String>,
+    // Counts the changes, for the fans to tell when to apply them again.
+    generation: u64,
+}
+
+// Settings changed on the running daemon, which take precedence over the
+// configuration file and the profiles until the daemon stops.
+#[derive(Default)]
+pub struct Settings {
+    inner: Mutex<Inner>,
+}
+
+fn overridden(overrides: &BTreeMap<Option<String>, FanConfig>, fan: &str, config: &FanConfig) -> FanConfig {
+    let mut config = config.clone();
+    for scope in [None, Some(String::from(fan))].iter() {
+        if let Some(setting) = overrides.get(scope) {
+            config = setting.clone().inherit(&config);
+        }
+    }
+    return config;
+}
+
+// The value as TOML, or as a TOML string for a bare word.
+fn parse(key: &str, value: &str, units: Units) -> Result<(FanConfig, String), SettingError> {
+    let (mut config, literal) = match toml::from_str::<FanConfig>(&format!("{} = {}", key, value)) {
+        Ok(config) => (config, String::from(value)),
+        Err(error) => {
+            let literal = format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+            let config = toml::from_str::<FanConfig>(&format!("{} = {}", key, literal))
+                .map_err(|_| SettingError::Invalid(String::from(key), error.to_string()))?;
+            (config, literal)
+        },
+    };
+    if let Some(ref mut step) = config.step {
+        step.sort_by_key(|pair| pair.temperature);
+    }
+    return Ok((config.normalize_units(units), literal));
+}
+
+// The change of brackets on a line, outside of strings and comments, to find
+// where a value spread over several lines ends.
+fn brackets(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (None, '#') => { break; },
+            (None, '"') | (None, '\'') => { quote = Some(c); },
+            (Some(open), _) if c == open => { quote = None; },
+            (None, '[') => { depth += 1; },
+            (None, ']') => { depth -= 1; },
+            _ => {},
+        };
+    }
+    return depth;
+}
+
+// Sets the key in the lines of its table, the top level being the lines up to
+// the first table. Everything else in the file, comments included, stays as
+// it is.
+fn set_line(lines: &mut Vec<String>, table: Option<&str>, key: &str, literal: &str) {
+    let setting = format!("{} = {}", key, literal);
+    let mut start = match table {
+        None => Some(0),
+        Some(_) => None,
+    };
+    let mut end = lines.len();
+    let mut found: Option<(usize, usize)> = None;
+    let mut depth = 0;
+    for (index, line) in lines.iter().enumerate() {
+        let top = depth == 0;
+        depth += brackets(line);
+        let trimmed = line.trim_start();
+        if top && trimmed.starts_with('[') {
+            if start.is_some() {
+                end = index;
+                break;
+            }
+            if table.is_some_and(|table| trimmed.trim_end().strip_prefix('[').and_then(|header| header.strip_suffix(']')) == Some(table)) {
+                start = Some(index + 1);
+            }
+            continue;
+        }
+        let assigned = trimmed.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with('='));
+        match found {
+            Some((first, last)) if !top && last + 1 == index => { found = Some((first, index)); },
+            None if top && start.is_some() && assigned => { found = Some((index, index)); },
+            _ => {},
+        };
+    }
+    match (start, found) {
+        (_, Some((first, last))) => { lines.splice(first..=last, std::iter::once(setting)); },
+        (Some(start), None) => {
+            // Comments right before the next table are about that one.
+            let mut position = end;
+            while position > start && (lines[position - 1].trim().is_empty() || lines[position - 1].trim_start().starts_with('#')) {
+                position -= 1;
+            }
+            lines.insert(position, setting);
+        },
+        (None, None) => {
+            lines.push(String::new());
+            lines.push(format!("[{}]", table.unwrap_or("")));
+            lines.push(setting);
+        },
+    };
+}
+
+// Written next to the file and renamed over it, so that the file is never
+// found half written.
+fn save(path: &str, table: Option<&str>, key: &str, literal: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => { return Err(Box::new(error)); },
+    };
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    set_line(&mut lines, table, key, literal);
+    let contents = format!("{}\n", lines.join("\n"));
+    toml::from_str::<Config>(&contents)?;
+    let temporary = format!("{}.new", path);
+    std::fs::write(&temporary, contents)?;
+    let mode = std::fs::metadata(path).map(|metadata| metadata.permissions().mode()).unwrap_or(0o644);
+    std::fs::set_permissions(&temporary, std::fs::Permissions::from_mode(mode))?;
+    std::fs::rename(&temporary, path)?;
+    return Ok(());
+}
+
+impl Settings {
+    // The path is only given for a TOML file, the only one saved to.
+    pub fn configure(&self, path: Option<String>, fans: &BTreeMap<String, FanConfig>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.path = path;
+        inner.fans = fans.clone();
+    }
+
+    // Only copied when something was changed.
+    pub fn apply<'a>(&self, fan: &str, config: &'a FanConfig) -> Cow<'a, FanConfig> {
+        let inner = self.inner.lock().unwrap();
+        if inner.overrides.is_empty() {
+            return Cow::Borrowed(config);
+        }
+        return Cow::Owned(overridden(&inner.overrides, fan, config));
+    }
+
+    pub fn generation(&self) -> u64 {
+        return self.inner.lock().unwrap().generation;
+    }
+
+    // The settings changed so far, one "key = value" per line.
+    pub fn list(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        return inner.values.iter().map(|(key, value)| format!("{} = {}", key, value)).collect::<Vec<String>>().join("\n");
+    }
+
+    // The key is a setting for every fan, or fan.<name>.<setting> for one,
+    // like in the configuration file.
+    pub fn set(&self, path: &str, value: &str, units: Units, persist: bool) -> Result<String, SettingError> {
+        let mut inner = self.inner.lock().unwrap();
+        let (fan, key) = match path.strip_prefix("fan.").and_then(|path| path.rsplit_once('.')) {
+            Some((fan, key)) => (Some(String::from(fan)), key),
+            None => (None, path),
+        };
+        if !SETTINGS.contains(&key) {
+            return Err(SettingError::Unknown(String::from(key)));
+        }
+        if let Some(ref fan) = fan {
+            if !inner.fans.contains_key(fan) {
+                return Err(SettingError::UnknownFan(fan.clone()));
+            }
+        }
+        let (setting, literal) = parse(key, value, units)?;
+        let mut overrides = inner.overrides.clone();
+        let scope = overrides.get(&fan).cloned();
+        overrides.insert(fan.clone(), match scope {
+            Some(scope) => setting.inherit(&scope),
+            None => setting,
+        });
+        for (name, config) in inner.fans.iter().filter(|(name, _)| fan.as_ref().is_none_or(|fan| fan == *name)) {
+            validate_fan(name, &overridden(&overrides, name, config)).map_err(|error| SettingError::Invalid(String::from(key), error.to_string()))?;
+        }
+        if persist {
+            let config_path = inner.path.clone().ok_or(SettingError::NotToml)?;
+            let table = fan.as_ref().map(|fan| format!("fan.{}", fan));
+            save(&config_path, table.as_deref(), key, &literal).map_err(|error| SettingError::Save(config_path, error.to_string()))?;
+        }
+        inner.overrides = overrides;
+        inner.generation += 1;
+        inner.values.insert(String::from(path), literal.clone());
+        log::info!("Setting {} to {}", path, literal);
+        return Ok(format!("{} = {}", path, literal));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(contents: &str, table: Option<&str>, key: &str, literal: &str) -> String {
+        let mut lines = contents.lines().map(String::from).collect();
+        set_line(&mut lines, table, key, literal);
+        return lines.join("\n");
+    }
+
+    #[test]
+    fn brackets_outside_strings_and_comments() {
+        assert_eq!(brackets("step = ["), 1);
+        assert_eq!(brackets("  { temperature = 50, fan_speed = 0 },"), 0);
+        assert_eq!(brackets("]"), -1);
+        assert_eq!(brackets("[[alerts]]"), 0);
+        assert_eq!(brackets("name = \"[rack\" # ["), 0);
+        assert_eq!(brackets("name = '\"[' ["), 1);
+    }
+
+    #[test]
+    fn replaces_a_setting() {
+        assert_eq!(set("min_speed = 10 # Quiet\n[fan.default]\nmin_speed = 20", None, "min_speed", "30"), "min_speed = 30\n[fan.default]\nmin_speed = 20");
+        assert_eq!(set("min_speed = 10\n[fan.default]\nmin_speed = 20", Some("fan.default"), "min_speed", "30"), "min_speed = 10\n[fan.default]\nmin_speed = 30");
+        assert_eq!(set("min_speed_limit = 10", None, "min_speed", "30"), "min_speed_limit = 10\nmin_speed = 30");
+    }
+
+    #[test]
+    fn replaces_every_line_of_an_array() {
+        assert_eq!(
+            set("step = [\n  { temperature = 50, fan_speed = 0 },\n]\nmin_speed = 10", None, "step", "[ { temperature = 60, fan_speed = 50 } ]"),
+            "step = [ { temperature = 60, fan_speed = 50 } ]\nmin_speed = 10",
+        );
+    }
+
+    #[test]
+    fn inserts_before_the_comments_of_the_next_table() {
+        assert_eq!(
+            set("min_speed = 10\n\n# The I2C bus.\n[i2c]\nbus = 1", None, "max_speed", "90"),
+            "min_speed = 10\nmax_speed = 90\n\n# The I2C bus.\n[i2c]\nbus = 1",
+        );
+    }
+
+    #[test]
+    fn adds_a_missing_table() {
+        assert_eq!(set("min_speed = 10", Some("fan.default"), "min_speed", "30"), "min_speed = 10\n\n[fan.default]\nmin_speed = 30");
+    }
+}
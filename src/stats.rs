@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// A sensor answering slower than this is counted, such as a drivetemp sensor
+// waking up a disk.
+pub const SENSOR_TIMEOUT: Duration = Duration::from_secs(1);
+
+// More edges than this on the button pin within a second are not a person
+// pressing it but a floating pin or a noisy line.
+const STORM_EDGES: u32 = 100;
+const STORM_WINDOW: Duration = Duration::from_secs(1);
+
+// What the hardware got wrong since the daemon started, to tell a flaky bus
+// from a hung fan controller or a bad sensor when the fan stops responding.
+#[derive(Default)]
+pub struct Stats {
+    pub i2c_write_failures: AtomicU64,
+    pub i2c_retries: AtomicU64,
+    pub i2c_read_failures: AtomicU64,
+    pub interrupt_storms: AtomicU64,
+    pub sensor_failures: AtomicU64,
+    pub sensor_timeouts: AtomicU64,
+}
+
+impl Stats {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Every counter with its name and what it counts.
+    pub fn counters(&self) -> [(&'static str, &'static str, u64); 6] {
+        let load = |counter: &AtomicU64| counter.load(Ordering::SeqCst);
+        return [
+            ("i2c_write_failures", "Fan speed writes that failed after all their retries", load(&self.i2c_write_failures)),
+            ("i2c_retries", "Fan speed writes retried after a failed attempt", load(&self.i2c_retries)),
+            ("i2c_read_failures", "Failed RPM reads from the fan controller", load(&self.i2c_read_failures)),
+            ("interrupt_storms", "Seconds with 100 edges or more on the button pin", load(&self.interrupt_storms)),
+            ("sensor_failures", "Failed temperature readings", load(&self.sensor_failures)),
+            ("sensor_timeouts", "Temperature readings slower than a second", load(&self.sensor_timeouts)),
+        ];
+    }
+
+    // Called for every edge on the button pin, from the interrupt thread.
+    // Counts a storm once when the edges of a window reach the limit.
+    pub fn edge(&self, window: &mut (Instant, u32)) {
+        let now = Instant::now();
+        if now.duration_since(window.0) >= STORM_WINDOW {
+            *window = (now, 0);
+        }
+        window.1 += 1;
+        if window.1 == STORM_EDGES {
+            log::warn!("Interrupt storm on the button pin, {} edges within {}s", STORM_EDGES, STORM_WINDOW.as_secs());
+            Stats::count(&self.interrupt_storms);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::mcu::Protocol;
+use crate::SharedState;
+
+#[derive(Serialize)]
+struct FanStatus {
+    name: String,
+    temperature: Option<f32>,
+    target_speed: u8,
+    fan_speed: u8,
+    rpm: Option<u16>,
+    profile: Option<String>,
+    critical: bool,
+}
+
+#[derive(Serialize)]
+struct BatteryStatus {
+    voltage: f32,
+    charge: f32,
+}
+
+#[derive(Serialize)]
+struct FirmwareStatus {
+    protocol: Protocol,
+    version: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    fans: Vec<FanStatus>,
+    profile_override: Option<String>,
+    battery: Option<BatteryStatus>,
+    firmware: Option<FirmwareStatus>,
+    errors: BTreeMap<&'static str, u64>,
+    unit: &'static str,
+}
+
+fn status(state: &SharedState) -> Status {
+    let fans = state.fans.lock().unwrap().iter().map(|(name, fan_state)| FanStatus {
+        name: name.clone(),
+        temperature: fan_state.temperature.map(|temperature| state.units.convert_celsius(temperature)),
+        target_speed: fan_state.target_speed,
+        fan_speed: fan_state.fan_speed,
+        rpm: fan_state.rpm,
+        profile: fan_state.profile.clone(),
+        critical: fan_state.critical,
+    }).collect();
+    let profile_override = state.profile_override.lock().unwrap().clone();
+    let battery = state.battery.lock().unwrap().map(|battery| BatteryStatus {
+        voltage: battery.voltage,
+        charge: battery.charge,
+    });
+    let firmware = state.mcu.as_ref().and_then(|mcu| mcu.firmware).map(|firmware| FirmwareStatus {
+        protocol: firmware.protocol,
+        version: firmware.version,
+    });
+    let errors = state.stats.counters().iter().map(|(name, _, count)| (*name, *count)).collect();
+    return Status { fans, profile_override, battery, firmware, errors, unit: state.units.symbol() };
+}
+
+// Temperatures are in the configured units, which the unit field names.
+pub fn status_json(state: &SharedState) -> Result<String, serde_json::Error> {
+    return serde_json::to_string(&status(state));
+}
+
+pub fn status_text(state: &SharedState) -> String {
+    let status = status(state);
+    let mut lines: Vec<String> = status.fans.iter().map(|fan| {
+        let mut line = match fan.temperature {
+            Some(temperature) => format!("Fan '{}': {:.1}{}, {}%", fan.name, temperature, status.unit, fan.fan_speed),
+            None => format!("Fan '{}': {}%", fan.name, fan.fan_speed),
+        };
+        if fan.target_speed != fan.fan_speed {
+            line.push_str(&format!(" (target {}%)", fan.target_speed));
+        }
+        if let Some(rpm) = fan.rpm {
+            line.push_str(&format!(", {}rpm", rpm));
+        }
+        if let Some(ref profile) = fan.profile {
+            line.push_str(&format!(", profile '{}'", profile));
+        }
+        return line;
+    }).collect();
+    if let Some(ref profile_override) = status.profile_override {
+        lines.push(format!("Profile override: {}", profile_override));
+    }
+    if let Some(ref battery) = status.battery {
+        lines.push(format!("Battery: {:.2}V {:.0}%", battery.voltage, battery.charge));
+    }
+    if let Some(firmware) = state.mcu.as_ref().and_then(|mcu| mcu.firmware) {
+        lines.push(format!("Fan controller: {}", firmware));
+    }
+    let errors: Vec<String> = status.errors.iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    if !errors.is_empty() {
+        lines.push(format!("Errors: {}", errors.join(", ")));
+    }
+    return lines.join("\n");
+}
@@ -0,0 +1,30 @@
+// The fan curve itself, on its own so that the curve benchmark can include it
+// without the rest of the daemon.
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone)]
+pub struct TempSpeedPair {
+    pub temperature: i16,
+    pub fan_speed: u8,
+}
+
+pub fn evaluate_curve(step_config: &[TempSpeedPair], temperature: f32) -> u8 {
+    for temperature_step in step_config.iter() {
+        if temperature < (temperature_step.temperature as f32) {
+            return temperature_step.fan_speed;
+        }
+    }
+    return 0;
+}
+
+// Rounded up, so that a fan never runs slower than asked for.
+pub fn quantize_speed(speed: u8, granularity: u8) -> u8 {
+    if granularity <= 1 {
+        return speed;
+    }
+    return (speed.min(100).div_ceil(granularity) * granularity).min(100);
+}
+
+pub fn clamp_speed(speed: u8, min_speed: Option<u8>, max_speed: Option<u8>) -> u8 {
+    return speed.max(min_speed.unwrap_or(u8::MIN)).min(max_speed.unwrap_or(u8::MAX));
+}
@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+// Longer than any number a sysfs attribute holds.
+const BUFFER: usize = 32;
+
+thread_local! {
+    // The fan tasks all run on the same thread, which keeps the files open
+    // between readings.
+    static FILES: RefCell<HashMap<String, File>> = RefCell::new(HashMap::new());
+}
+
+fn read_attribute(path: &str, buffer: &mut [u8]) -> std::io::Result<usize> {
+    return FILES.with(|files| {
+        let mut files = files.borrow_mut();
+        if !files.contains_key(path) {
+            files.insert(String::from(path), File::open(path)?);
+        }
+        let read = files[path].read_at(buffer, 0);
+        if read.is_err() {
+            files.remove(path);
+        }
+        return read;
+    });
+}
+
+// A sysfs attribute is generated anew by every read from its start, so it is
+// opened once and read into a buffer on the stack, without a system call to
+// open and close it for every reading. One that fails to read is opened again
+// the next time, for sensors that come and go. Other files are opened every
+// time, a program replacing them would otherwise leave the old one read.
+pub fn read_number(path: &str) -> Result<f32, Box<dyn std::error::Error>> {
+    if !path.starts_with("/sys/") {
+        return Ok(std::fs::read_to_string(path)?.trim().parse::<f32>()?);
+    }
+    let mut buffer = [0u8; BUFFER];
+    let length = read_attribute(path, &mut buffer)?;
+    return Ok(std::str::from_utf8(&buffer[..length])?.trim().parse::<f32>()?);
+}
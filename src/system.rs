@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use std::process::{Command, ExitStatus};
+
+// How the daemon powers off, reboots and runs hooks, which depends on the init
+// system. "dry_run" only logs what it would do, for trying a configuration out
+// and for the tests.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Init {
+    #[default]
+    Systemd,
+    Sysvinit,
+    Openrc,
+    DryRun,
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct SystemConfig {
+    pub init: Option<Init>,
+}
+
+#[derive(Debug)]
+pub enum SystemError {
+    Failed(String, ExitStatus),
+}
+
+impl std::error::Error for SystemError {}
+
+impl std::fmt::Display for SystemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SystemError::Failed(command, status) => write!(f, "{} failed with {}", command, status),
+        }
+    }
+}
+
+pub trait SystemActions: Send + Sync {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Whether logind runs the shutdown, which is the only one to know about
+    // the inhibitors of other programs.
+    fn logind(&self) -> bool {
+        return false;
+    }
+
+    // Hooks do not wait for each other nor hold the daemon up, the runtime
+    // reaps them once they exit.
+    fn run_hook(&self, path: &str, environment: Vec<(&'static str, String)>) -> Result<(), Box<dyn std::error::Error>> {
+        tokio::process::Command::new(path).envs(environment).spawn()?;
+        return Ok(());
+    }
+}
+
+// The shutdown commands return as soon as the shutdown is under way.
+fn run(program: &str, arguments: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new(program).args(arguments).status()?;
+    if !status.success() {
+        let command = std::iter::once(program).chain(arguments.iter().copied()).collect::<Vec<&str>>().join(" ");
+        return Err(Box::new(SystemError::Failed(command, status)));
+    }
+    return Ok(());
+}
+
+struct Systemd;
+
+impl SystemActions for Systemd {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("systemctl", &["poweroff"]);
+    }
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("systemctl", &["reboot"]);
+    }
+
+    fn logind(&self) -> bool {
+        return true;
+    }
+}
+
+struct Sysvinit;
+
+impl SystemActions for Sysvinit {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("shutdown", &["-h", "now"]);
+    }
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("shutdown", &["-r", "now"]);
+    }
+}
+
+// openrc-init has no shutdown of its own, OpenRC on top of sysvinit is the
+// same as sysvinit.
+struct Openrc;
+
+impl SystemActions for Openrc {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("openrc-shutdown", &["--poweroff", "now"]);
+    }
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return run("openrc-shutdown", &["--reboot", "now"]);
+    }
+}
+
+struct DryRun;
+
+impl SystemActions for DryRun {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        log::info!("Dry run, not powering off");
+        return Ok(());
+    }
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        log::info!("Dry run, not rebooting");
+        return Ok(());
+    }
+
+    fn run_hook(&self, path: &str, environment: Vec<(&'static str, String)>) -> Result<(), Box<dyn std::error::Error>> {
+        let environment: Vec<String> = environment.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        log::info!("Dry run, not running the hook {} with {}", path, environment.join(" "));
+        return Ok(());
+    }
+}
+
+impl Init {
+    pub fn actions(self) -> Box<dyn SystemActions> {
+        return match self {
+            Init::Systemd => Box::new(Systemd),
+            Init::Sysvinit => Box::new(Sysvinit),
+            Init::Openrc => Box::new(Openrc),
+            Init::DryRun => Box::new(DryRun),
+        };
+    }
+}
+
+impl Default for Box<dyn SystemActions> {
+    fn default() -> Box<dyn SystemActions> {
+        return Init::default().actions();
+    }
+}
+
+// Records what the daemon asks of the system instead of doing it, optionally
+// failing to, for the tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Recording {
+    pub calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    pub fail: bool,
+}
+
+#[cfg(test)]
+impl Recording {
+    fn record(&self, call: String) -> Result<(), Box<dyn std::error::Error>> {
+        self.calls.lock().unwrap().push(call);
+        if self.fail {
+            return Err(Box::from("failed on purpose"));
+        }
+        return Ok(());
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        return self.calls.lock().unwrap().clone();
+    }
+}
+
+#[cfg(test)]
+impl SystemActions for Recording {
+    fn poweroff(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return self.record(String::from("poweroff"));
+    }
+
+    fn reboot(&self) -> Result<(), Box<dyn std::error::Error>> {
+        return self.record(String::from("reboot"));
+    }
+
+    fn run_hook(&self, path: &str, environment: Vec<(&'static str, String)>) -> Result<(), Box<dyn std::error::Error>> {
+        let environment: Vec<String> = environment.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        return self.record(format!("{} {}", path, environment.join(" ")));
+    }
+}
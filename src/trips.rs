@@ -0,0 +1,82 @@
+// The trip points of a kernel thermal zone, at which the kernel itself acts:
+// passive and hot ones throttle the CPU, the critical one powers the system
+// off. Active trip points are for the cooling devices of the kernel, which
+// the daemon replaces, and are left out.
+
+pub const DEFAULT_THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0";
+
+#[derive(Default, Debug, PartialEq)]
+pub struct TripPoints {
+    // The lowest passive or hot trip point, in degrees Celsius.
+    pub throttle: Option<i16>,
+    pub critical: Option<i16>,
+}
+
+#[derive(Debug)]
+pub enum TripError {
+    NoTripPoints(String),
+}
+
+impl std::error::Error for TripError {}
+
+impl std::fmt::Display for TripError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TripError::NoTripPoints(zone) => write!(f, "No passive, hot or critical trip point in {}", zone),
+        }
+    }
+}
+
+pub fn read_trip_points(zone: &str) -> Result<TripPoints, Box<dyn std::error::Error>> {
+    let mut trip_points = TripPoints::default();
+    for index in 0.. {
+        let kind = match std::fs::read_to_string(format!("{}/trip_point_{}_type", zone, index)) {
+            Ok(kind) => kind,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => { break; },
+            Err(error) => { return Err(Box::new(error)); },
+        };
+        let temperature = std::fs::read_to_string(format!("{}/trip_point_{}_temp", zone, index))?.trim().parse::<i32>()?;
+        let temperature = (temperature / 1000).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let lowest = |current: Option<i16>| Some(current.map_or(temperature, |current| current.min(temperature)));
+        match kind.trim() {
+            "passive" | "hot" => { trip_points.throttle = lowest(trip_points.throttle); },
+            "critical" => { trip_points.critical = lowest(trip_points.critical); },
+            _ => {},
+        };
+    }
+    if trip_points == TripPoints::default() {
+        return Err(Box::new(TripError::NoTripPoints(String::from(zone))));
+    }
+    return Ok(trip_points);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(name: &str, trip_points: &[(&str, i32)]) -> std::path::PathBuf {
+        let zone = std::env::temp_dir().join(format!("argononed-trips-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&zone).unwrap();
+        for (index, (kind, temperature)) in trip_points.iter().enumerate() {
+            std::fs::write(zone.join(format!("trip_point_{}_type", index)), format!("{}\n", kind)).unwrap();
+            std::fs::write(zone.join(format!("trip_point_{}_temp", index)), format!("{}\n", temperature)).unwrap();
+        }
+        return zone;
+    }
+
+    #[test]
+    fn lowest_trip_points() {
+        let zone = zone("lowest", &[("active", 50000), ("passive", 85000), ("hot", 80500), ("critical", 110000), ("critical", 105000)]);
+        let trip_points = read_trip_points(zone.to_str().unwrap());
+        std::fs::remove_dir_all(&zone).unwrap();
+        assert_eq!(trip_points.unwrap(), TripPoints { throttle: Some(80), critical: Some(105) });
+    }
+
+    #[test]
+    fn only_active_trip_points() {
+        let zone = zone("active", &[("active", 50000), ("active", 60000)]);
+        let error = read_trip_points(zone.to_str().unwrap()).unwrap_err();
+        std::fs::remove_dir_all(&zone).unwrap();
+        assert!(matches!(error.downcast_ref::<TripError>(), Some(TripError::NoTripPoints(_))));
+    }
+}
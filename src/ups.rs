@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::action::Action;
+use crate::alerts::Alert;
+use crate::hardware::I2c;
+use crate::SharedState;
+
+// The fuel gauge of the UPS speaks the MAX17048 register map.
+const UPS_ADDR: u16 = 0x36;
+const VCELL: u8 = 0x02;
+const SOC: u8 = 0x04;
+const CRATE: u8 = 0x16;
+const VCELL_VOLTS_PER_BIT: f32 = 78.125e-6;
+const CRATE_PERCENT_PER_HOUR_PER_BIT: f32 = 0.208;
+
+#[derive(Deserialize, Clone)]
+pub struct UpsConfig {
+    address: Option<u16>,
+    interval: Option<u64>,
+    shutdown_charge: Option<f32>,
+}
+
+#[derive(Clone, Copy)]
+pub struct BatteryState {
+    pub voltage: f32,
+    pub charge: f32,
+    // Percent per hour, negative while the battery discharges.
+    pub rate: f32,
+}
+
+// Registers are big-endian, SMBus words little-endian.
+fn read_battery(i2c_interface: &Mutex<I2c>, address: u16) -> Result<BatteryState, Box<dyn std::error::Error>> {
+    let mut i2c_interface = i2c_interface.lock().unwrap();
+    i2c_interface.set_slave_address(address)?;
+    let voltage = i2c_interface.smbus_read_word_swapped(VCELL)? as f32 * VCELL_VOLTS_PER_BIT;
+    let charge = i2c_interface.smbus_read_word_swapped(SOC)? as f32 / 256.0;
+    let rate = i2c_interface.smbus_read_word_swapped(CRATE)? as i16 as f32 * CRATE_PERCENT_PER_HOUR_PER_BIT;
+    return Ok(BatteryState { voltage, charge: charge.min(100.0), rate });
+}
+
+// Only a battery running down powers the Pi off. One still low but charging
+// again, the power being back, would otherwise power off every boot until it
+// is above the shutdown charge. Returns whether it asked for the poweroff.
+fn power_off_when_low(battery: BatteryState, shutdown_charge: f32, state: &SharedState) -> Result<bool, Box<dyn std::error::Error>> {
+    if battery.charge > shutdown_charge || battery.rate >= 0.0 {
+        return Ok(false);
+    }
+    log::error!("Battery at {:.0}% and discharging, below the shutdown charge, powering off", battery.charge);
+    Action::Poweroff.run("battery", state)?;
+    return Ok(true);
+}
+
+pub async fn ups_check(config: UpsConfig, i2c_interface: Arc<Mutex<I2c>>, state: SharedState) -> Result<(), Box<dyn std::error::Error>> {
+    let address = config.address.unwrap_or(UPS_ADDR);
+    let interval = Duration::from_secs(config.interval.unwrap_or(30));
+    let shutdown_charge = config.shutdown_charge.unwrap_or(5.0);
+    let mut shutdown_requested = false;
+    let mut low = false;
+    loop {
+        let battery = read_battery(&i2c_interface, address)?;
+        *state.battery.lock().unwrap() = Some(battery);
+        if let Some(low_battery_charge) = state.alerts.low_battery_charge() {
+            if battery.charge <= low_battery_charge && !low {
+                state.alerts.send(Alert::LowBattery, format!("Battery at {:.0}% ({:.2}V)", battery.charge, battery.voltage));
+            }
+            low = battery.charge <= low_battery_charge;
+        }
+        if !shutdown_requested {
+            shutdown_requested = power_off_when_low(battery, shutdown_charge, &state)?;
+        }
+        if state.termination.wait(interval).await {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::Recording;
+    use crate::State;
+
+    fn power_off(charge: f32, rate: f32) -> Vec<String> {
+        let recording = Recording::default();
+        let state = Arc::new(State { system: Box::new(recording.clone()), ..State::default() });
+        let battery = BatteryState { voltage: 3.4, charge, rate };
+        assert_eq!(power_off_when_low(battery, 5.0, &state).unwrap(), !recording.calls().is_empty());
+        return recording.calls();
+    }
+
+    #[test]
+    fn low_and_discharging_powers_off() {
+        assert_eq!(power_off(4.0, -10.4), ["poweroff"]);
+        assert_eq!(power_off(5.0, -0.208), ["poweroff"]);
+    }
+
+    #[test]
+    fn low_and_charging_keeps_running() {
+        assert!(power_off(3.0, 20.8).is_empty());
+        assert!(power_off(3.0, 0.0).is_empty());
+    }
+
+    #[test]
+    fn above_the_shutdown_charge_keeps_running() {
+        assert!(power_off(40.0, -10.4).is_empty());
+    }
+}
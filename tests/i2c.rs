@@ -0,0 +1,688 @@
+// Runs the daemon against a simulated fan controller and checks the bytes it
+// writes. Without the hardware feature the simulated bus of the daemon itself
+// records the writes:
+//
+//     cargo test --no-default-features
+//
+// With it, the tests marked ignored drive the i2c-stub kernel module instead.
+// They need a Pi, root and the module loaded, with the number of the bus it
+// created in ARGONONED_TEST_I2C_BUS:
+//
+//     modprobe i2c-stub chip_addr=0x1a
+//     ARGONONED_TEST_I2C_BUS=11 cargo test -- --ignored
+
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const CURVE: &str = r#"
+step = [
+  { temperature = 50, fan_speed = 0 },
+  { temperature = 60, fan_speed = 50 },
+  { temperature = 32767, fan_speed = 100 },
+]
+"#;
+
+// Polls the condition until it holds, instead of sleeping for a time that may
+// not be enough on a loaded machine.
+fn wait_until(what: impl Fn() -> String, condition: impl Fn() -> bool) {
+    let started = Instant::now();
+    while !condition() {
+        if started.elapsed() > TIMEOUT {
+            panic!("{}", what());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// What a test runs the daemon with: the curve above on the register protocol,
+// then the settings, I2C settings and tables of the test.
+struct Setup {
+    settings: Vec<String>,
+    curve: bool,
+    protocol: Option<&'static str>,
+    i2c: Vec<String>,
+    tables: Vec<String>,
+    environment: Vec<(&'static str, String)>,
+}
+
+impl Setup {
+    fn new() -> Setup {
+        return Setup { settings: Vec::new(), curve: true, protocol: Some("registers"), i2c: Vec::new(), tables: Vec::new(), environment: Vec::new() };
+    }
+
+    fn protocol(mut self, protocol: &'static str) -> Setup {
+        self.protocol = Some(protocol);
+        return self;
+    }
+
+    fn env(mut self, name: &'static str, value: &str) -> Setup {
+        self.environment.push((name, String::from(value)));
+        return self;
+    }
+
+    fn config(&self) -> String {
+        let mut config = self.settings.iter().map(|setting| format!("{}\n", setting)).collect::<String>();
+        if self.curve {
+            config.push_str(CURVE);
+        }
+        if self.protocol.is_some() || !self.i2c.is_empty() {
+            config.push_str("[i2c]\n");
+        }
+        if let Some(protocol) = self.protocol {
+            config.push_str(&format!("protocol = \"{}\"\n", protocol));
+        }
+        for setting in self.i2c.iter().chain(self.tables.iter()) {
+            config.push_str(&format!("{}\n", setting));
+        }
+        return config;
+    }
+
+    fn start(&self, name: &str, temperature: i32) -> Daemon {
+        return Daemon::start(name, temperature, &self.environment, &self.config());
+    }
+}
+
+#[cfg(not(feature = "hardware"))]
+impl Setup {
+    // A top level setting, or several lines of them.
+    fn set(mut self, setting: &str) -> Setup {
+        self.settings.push(String::from(setting));
+        return self;
+    }
+
+    fn without_curve(mut self) -> Setup {
+        self.curve = false;
+        return self;
+    }
+
+    fn detect_protocol(mut self) -> Setup {
+        self.protocol = None;
+        return self;
+    }
+
+    fn i2c(mut self, setting: &str) -> Setup {
+        self.i2c.push(String::from(setting));
+        return self;
+    }
+
+    // A table with its header, after the I2C settings.
+    fn table(mut self, table: &str) -> Setup {
+        self.tables.push(String::from(table));
+        return self;
+    }
+}
+
+struct Daemon {
+    child: Option<Child>,
+    directory: PathBuf,
+}
+
+impl Daemon {
+    // Everything lives in a fresh directory, which {directory} in the
+    // configuration stands for: the temperature source, the control socket,
+    // the state file and the lock are always set up in there.
+    fn start(name: &str, temperature: i32, environment: &[(&str, String)], config: &str) -> Daemon {
+        let directory = std::env::temp_dir().join(format!("argononed-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = format!(
+            "sources = [ {{ type = \"file\", path = \"{{directory}}/temperature\" }} ]\n{}\n[control]\nsocket = \"{{directory}}/argononed.sock\"\n[persist]\npath = \"{{directory}}/argononed.state\"\n[startup]\nlock_file = \"{{directory}}/argononed.lock\"\n",
+            config,
+        ).replace("{directory}", directory.to_str().unwrap());
+        std::fs::write(directory.join("argononed.conf"), config).unwrap();
+        let mut daemon = Daemon { child: None, directory };
+        daemon.set_temperature(temperature);
+        daemon.child = Some(Command::new(env!("CARGO_BIN_EXE_argonone"))
+            .arg("--config").arg(daemon.directory.join("argononed.conf"))
+            .arg("daemon")
+            .env("ARGONONED_I2C_TRACE", daemon.directory.join("trace"))
+            .env("ARGONONED_LOG_LEVEL", "error")
+            .envs(environment.iter().cloned())
+            .spawn()
+            .unwrap());
+        return daemon;
+    }
+
+    fn set_temperature(&self, celsius: i32) {
+        std::fs::write(self.directory.join("temperature"), format!("{}\n", celsius * 1000)).unwrap();
+    }
+
+    fn command(&self, request: &str) -> String {
+        let socket = self.directory.join("argononed.sock");
+        let started = Instant::now();
+        let mut stream = loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => break stream,
+                Err(error) if started.elapsed() > TIMEOUT => panic!("no control socket: {}", error),
+                Err(_) => std::thread::sleep(Duration::from_millis(50)),
+            };
+        };
+        writeln!(stream, "{}", request).unwrap();
+        let mut response = String::new();
+        for line in BufReader::new(stream).lines() {
+            let line = line.unwrap();
+            if line.is_empty() {
+                break;
+            }
+            response.push_str(&line);
+            response.push('\n');
+        }
+        return response;
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+            assert!(child.wait().unwrap().success());
+        }
+    }
+}
+
+#[cfg(not(feature = "hardware"))]
+impl Daemon {
+    fn trace(&self) -> Vec<String> {
+        let trace = std::fs::read_to_string(self.directory.join("trace")).unwrap_or_default();
+        return trace.lines().map(String::from).collect();
+    }
+
+    // Refreshes write the same speed again, only the changes are compared.
+    fn writes(&self) -> Vec<String> {
+        let mut writes = self.trace();
+        writes.dedup();
+        return writes;
+    }
+
+    fn wait_for_writes(&self, expected: &[&str]) {
+        wait_until(|| format!("expected writes {:?}, got {:?}", expected, self.writes()), || self.writes() == expected);
+    }
+
+    // Until the answer to the request contains the expected text.
+    fn wait_for(&self, request: &str, expected: &str) {
+        wait_until(|| format!("expected {:?} in {:?}", expected, self.command(request)), || self.command(request).contains(expected));
+    }
+
+    // The events of the daemon, one JSON object per line.
+    fn subscribe(&self) -> impl Iterator<Item = String> {
+        let mut stream = UnixStream::connect(self.directory.join("argononed.sock")).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        writeln!(stream, "subscribe").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(Result::unwrap);
+        assert_eq!(lines.next().unwrap(), "ok");
+        return lines;
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn register_protocol_is_detected() {
+    let mut daemon = Setup::new().detect_protocol().start("register-protocol", 55);
+    daemon.wait_for_writes(&["0x1a 80 01", "0x1a 80 00", "0x1a 80 32"]);
+    daemon.stop();
+    assert_eq!(daemon.writes(), ["0x1a 80 01", "0x1a 80 00", "0x1a 80 32", "0x1a 80 00"]);
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn raw_protocol_writes_register_0() {
+    let mut daemon = Setup::new().protocol("raw").start("raw-protocol", 55);
+    daemon.wait_for_writes(&["0x1a 00 32"]);
+    daemon.stop();
+    assert_eq!(daemon.writes(), ["0x1a 00 32", "0x1a 00 00"]);
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn address_from_the_environment() {
+    let mut daemon = Setup::new().protocol("raw").env("ARGONONED_I2C_ADDRESS", "0x1b").start("environment", 55);
+    daemon.wait_for_writes(&["0x1b 00 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn temperature_correction() {
+    let mut daemon = Setup::new()
+        .table("[fan.corrected]\nsources = [ { type = \"file\", path = \"{directory}/temperature\", offset = 10, scale = 0.5 } ]")
+        .start("temperature-correction", 90);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn fahrenheit_steps() {
+    let mut daemon = Setup::new().without_curve()
+        .set("units = \"fahrenheit\"\nstep = [ { temperature = 122, fan_speed = 0 }, { temperature = 140, fan_speed = 50 } ]")
+        .start("fahrenheit", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn speed_granularity_rounds_up() {
+    let mut daemon = Setup::new().without_curve()
+        .set("speed_granularity = 10\nstep = [ { temperature = 50, fan_speed = 0 }, { temperature = 60, fan_speed = 45 } ]")
+        .start("granularity", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn kick_start_from_standstill() {
+    let mut daemon = Setup::new().set("kick_start_speed = 80").start("kick-start", 55);
+    daemon.wait_for_writes(&["0x1a 80 50", "0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn temperature_transitions() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("transitions", 45);
+    daemon.wait_for_writes(&["0x1a 80 00"]);
+    daemon.set_temperature(65);
+    daemon.wait_for_writes(&["0x1a 80 00", "0x1a 80 64"]);
+    daemon.set_temperature(55);
+    daemon.wait_for_writes(&["0x1a 80 00", "0x1a 80 64", "0x1a 80 32"]);
+    daemon.stop();
+    assert_eq!(daemon.writes(), ["0x1a 80 00", "0x1a 80 64", "0x1a 80 32", "0x1a 80 00"]);
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn speed_override_and_back() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("speed-override", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    assert_eq!(daemon.command("set-speed 100"), "ok\n100%\n");
+    daemon.wait_for_writes(&["0x1a 80 32", "0x1a 80 64"]);
+    assert_eq!(daemon.command("set-speed auto"), "ok\nauto\n");
+    daemon.wait_for_writes(&["0x1a 80 32", "0x1a 80 64", "0x1a 80 32"]);
+    daemon.stop();
+}
+
+// Well within the default delay of 30s between cycles.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn profile_switch_applies_right_away() {
+    let mut daemon = Setup::new().table("[profile.full]\ndynamic = false\nconst_fan_speed = 100").start("profile-switch", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    assert_eq!(daemon.command("profile full"), "ok\nfull\n");
+    daemon.wait_for_writes(&["0x1a 80 32", "0x1a 80 64"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn second_daemon_is_refused() {
+    let mut daemon = Setup::new().start("second-daemon", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    let second = Command::new(env!("CARGO_BIN_EXE_argonone"))
+        .arg("--config").arg(daemon.directory.join("argononed.conf"))
+        .arg("daemon")
+        .env("ARGONONED_LOG_LEVEL", "error")
+        .output()
+        .unwrap();
+    assert!(!second.status.success());
+    assert!(String::from_utf8_lossy(&second.stderr).contains("Another daemon is already running as pid"));
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn critical_temperature_alert() {
+    use std::io::Read;
+    use std::net::TcpListener;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut daemon = Setup::new()
+        .set("critical_temperature = 70")
+        .table(&format!("[alerts]\nurl = \"http://{}/alert\"\ntemplate = '{{\"text\": \"{{event}}: {{message}}\"}}'", listener.local_addr().unwrap()))
+        .start("alert", 80);
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut request = String::new();
+    let mut buffer = [0u8; 1024];
+    while !request.ends_with('}') {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0, "incomplete request: {}", request);
+        request.push_str(&String::from_utf8_lossy(&buffer[..read]));
+    }
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    assert!(request.starts_with("POST /alert HTTP/1.1\r\n"));
+    assert!(request.ends_with("\r\n\r\n{\"text\": \"critical: Fan 'default' reads 80.0C, above the critical temperature\"}"));
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn imported_config() {
+    let python_config = std::env::temp_dir().join(format!("argononed-test-import-{}.conf", std::process::id()));
+    std::fs::write(&python_config, "# Start below:\n55=10\n60=55\n65=100\n").unwrap();
+    let imported = Command::new(env!("CARGO_BIN_EXE_argonone"))
+        .arg("import-config").arg(&python_config)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&python_config).unwrap();
+    assert!(imported.status.success());
+    let mut daemon = Setup::new().without_curve()
+        .set("delay_on_change = 1")
+        .set(&String::from_utf8(imported.stdout).unwrap())
+        .start("import", 57);
+    daemon.wait_for_writes(&["0x1a 80 0a"]);
+    daemon.set_temperature(65);
+    daemon.wait_for_writes(&["0x1a 80 0a", "0x1a 80 64"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn status_as_json() {
+    let mut daemon = Setup::new().start("status", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.wait_for("status json", "\"name\":\"default\",\"temperature\":55.0,\"target_speed\":50,\"fan_speed\":50");
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn sensor_failures_are_counted() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("errors", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    assert!(daemon.command("status json").contains("\"sensor_failures\":0"));
+    std::fs::remove_file(daemon.directory.join("temperature")).unwrap();
+    daemon.wait_for("status", "Errors: 1 sensor_failures");
+    daemon.stop();
+}
+
+#[test]
+fn completions_and_manpage() {
+    let generate = |arguments: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_argonone")).args(arguments).output().unwrap();
+        assert!(output.status.success());
+        return String::from_utf8(output.stdout).unwrap();
+    };
+    let bash = generate(&["completions", "bash"]);
+    assert!(bash.contains("COMPREPLY=($(compgen -W \"toml yaml json\" -- \"${cur}\"))"));
+    assert!(bash.contains("complete -F _argonone -o bashdefault -o default argonone\n"));
+    assert!(generate(&["completions", "zsh"]).starts_with("#compdef argonone\n"));
+    assert!(generate(&["completions", "fish"]).contains("-f -a \"check\" -d 'Check the configuration without running the daemon'"));
+    let manpage = generate(&["manpage"]);
+    assert!(manpage.contains(".TH argonone 1 "));
+    assert!(manpage.contains("\\fB\\-c\\fR, \\fB\\-\\-config\\fR \\fI<CONFIG>\\fR"));
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn trip_points_set_the_critical_temperature() {
+    let zone = std::env::temp_dir().join(format!("argononed-test-zone-{}", std::process::id()));
+    std::fs::create_dir_all(&zone).unwrap();
+    for (index, (kind, temperature)) in [("active", 50000), ("passive", 52000), ("critical", 110000)].iter().enumerate() {
+        std::fs::write(zone.join(format!("trip_point_{}_type", index)), format!("{}\n", kind)).unwrap();
+        std::fs::write(zone.join(format!("trip_point_{}_temp", index)), format!("{}\n", temperature)).unwrap();
+    }
+    let mut daemon = Setup::new().set(&format!("trip_points = true\nthermal_zone = \"{}\"", zone.display())).start("trip-points", 55);
+    daemon.wait_for_writes(&["0x1a 80 64"]);
+    daemon.stop();
+    std::fs::remove_dir_all(&zone).unwrap();
+}
+
+// The SSD source holds the speed it asked for 3s, its own delay, when the fan
+// would slow down after 1s.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn source_curves_and_delays() {
+    let ssd = std::env::temp_dir().join(format!("argononed-test-ssd-{}", std::process::id()));
+    std::fs::write(&ssd, "45000\n").unwrap();
+    let mut daemon = Setup::new()
+        .set("delay_on_change = 1")
+        .table(&format!(
+            "[fan.default]\nsources = [ {{ type = \"file\", path = \"{{directory}}/temperature\" }}, {{ type = \"file\", path = \"{}\", delay_on_change = 3, step = [ {{ temperature = 40, fan_speed = 0 }}, {{ temperature = 32767, fan_speed = 30 }} ] }} ]",
+            ssd.display(),
+        ))
+        .start("source-curves", 45);
+    daemon.wait_for_writes(&["0x1a 80 1e"]);
+    daemon.set_temperature(65);
+    daemon.wait_for_writes(&["0x1a 80 1e", "0x1a 80 64"]);
+    daemon.set_temperature(45);
+    daemon.wait_for_writes(&["0x1a 80 1e", "0x1a 80 64", "0x1a 80 32", "0x1a 80 1e"]);
+    let cooled = Instant::now();
+    std::fs::write(&ssd, "35000\n").unwrap();
+    daemon.wait_for_writes(&["0x1a 80 1e", "0x1a 80 64", "0x1a 80 32", "0x1a 80 1e", "0x1a 80 00"]);
+    assert!(cooled.elapsed() >= Duration::from_secs(3));
+    daemon.stop();
+    std::fs::remove_file(&ssd).unwrap();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn slowing_down_steps_through_the_curve() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("step-down", 65);
+    daemon.wait_for_writes(&["0x1a 80 64"]);
+    daemon.set_temperature(45);
+    daemon.wait_for_writes(&["0x1a 80 64", "0x1a 80 32", "0x1a 80 00"]);
+    daemon.stop();
+}
+
+// The status only shows a reading once the cycle that read it is done.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn hysteresis_holds_the_speed() {
+    let mut daemon = Setup::new().set("delay_on_change = 1\nhysteresis = 3").start("hysteresis", 65);
+    daemon.wait_for_writes(&["0x1a 80 64"]);
+    daemon.set_temperature(58);
+    daemon.wait_for("status json", "\"temperature\":58.0,\"target_speed\":100,\"fan_speed\":100");
+    assert_eq!(daemon.writes(), ["0x1a 80 64"]);
+    daemon.set_temperature(56);
+    daemon.wait_for_writes(&["0x1a 80 64", "0x1a 80 32"]);
+    daemon.stop();
+}
+
+// The second of 45C is the dip itself, shorter than the delay, not a wait for
+// the daemon.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn short_dip_keeps_the_speed() {
+    let mut daemon = Setup::new().set("delay_on_change = 3").start("dip", 65);
+    daemon.wait_for_writes(&["0x1a 80 64"]);
+    daemon.set_temperature(45);
+    std::thread::sleep(Duration::from_secs(1));
+    daemon.set_temperature(70);
+    daemon.wait_for("status json", "\"temperature\":70.0,\"target_speed\":100,\"fan_speed\":100");
+    assert_eq!(daemon.writes(), ["0x1a 80 64"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn cluster_reports_drive_the_fan() {
+    use std::net::UdpSocket;
+
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let listen = peer.local_addr().unwrap();
+    drop(peer);
+    let mut daemon = Setup::new()
+        .set("delay_on_change = 1")
+        .table(&format!("[cluster]\ntoken = \"secret\"\nlisten = \"{}\"", listen))
+        .table("[fan.stack]\nsources = [ { type = \"file\", path = \"{directory}/temperature\" }, { type = \"cluster\" } ]")
+        .start("cluster", 40);
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    wait_until(|| String::from("no write for the reports"), || {
+        peer.send_to(b"argonone1 wrong 65.0 intruder", listen).unwrap();
+        peer.send_to(b"argonone1 secret 55.0 pi2", listen).unwrap();
+        return daemon.writes().len() == 2;
+    });
+    assert_eq!(daemon.writes(), ["0x1a 80 00", "0x1a 80 32"]);
+    daemon.stop();
+}
+
+// No peer ever reports, the file source alone drives the fan.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn quiet_cluster_is_left_out() {
+    let mut daemon = Setup::new()
+        .set("max_sensor_failures = 1")
+        .table("[cluster]\ntoken = \"secret\"")
+        .table("[fan.stack]\nsources = [ { type = \"file\", path = \"{directory}/temperature\" }, { type = \"cluster\" } ]")
+        .start("quiet-cluster", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    wait_until(|| String::from("the cluster was not left out"), || !daemon.command("status json").contains("\"sensor_failures\":0"));
+    assert_eq!(daemon.writes(), ["0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn health_exit_codes() {
+    let mut daemon = Setup::new().start("health", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    let config_path = daemon.directory.join("argononed.conf");
+    let health = || Command::new(env!("CARGO_BIN_EXE_argonone"))
+        .arg("--config").arg(&config_path)
+        .arg("health")
+        .output()
+        .unwrap();
+    let healthy = health();
+    assert_eq!(healthy.status.code(), Some(0));
+    assert_eq!(String::from_utf8(healthy.stdout).unwrap(), "healthy\n");
+    daemon.stop();
+    let unreachable = health();
+    assert_eq!(unreachable.status.code(), Some(3));
+    assert!(String::from_utf8(unreachable.stdout).unwrap().starts_with("unreachable: "));
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn subscribers_get_speed_changes() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("subscribe", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    let mut events = daemon.subscribe();
+    daemon.set_temperature(65);
+    let event = events.find(|line| line.contains("\"event\":\"speed_change\"")).unwrap();
+    assert_eq!(event, r#"{"event":"speed_change","fan":"default","old_speed":50,"speed":100}"#);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn settings_change_at_runtime() {
+    let mut daemon = Setup::new().start("settings", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    assert_eq!(daemon.command("set min_speed 60"), "ok\nmin_speed = 60\n");
+    daemon.wait_for_writes(&["0x1a 80 32", "0x1a 80 3c"]);
+    assert_eq!(daemon.command("set --save fan.default.delay_on_change 5"), "ok\nfan.default.delay_on_change = 5\n");
+    assert_eq!(daemon.command("set hysteresis 3"), "ok\nhysteresis = 3\n");
+    assert!(daemon.command("set noise 3").starts_with("error: Unknown setting 'noise'"));
+    assert!(daemon.command("set max_speed 20").starts_with("error: Invalid value for max_speed"));
+    assert_eq!(daemon.command("set"), "ok\nfan.default.delay_on_change = 5\nhysteresis = 3\nmin_speed = 60\n");
+    let saved = std::fs::read_to_string(daemon.directory.join("argononed.conf")).unwrap();
+    assert!(saved.starts_with("sources = "));
+    assert!(saved.ends_with("\n[fan.default]\ndelay_on_change = 5\n"));
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn dry_run_shutdown() {
+    let mut daemon = Setup::new().set("delay_on_change = 1\nshutdown_temperature = 70").table("[system]\ninit = \"dry_run\"").start("dry-run", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    let mut events = daemon.subscribe();
+    daemon.set_temperature(75);
+    let event = events.find(|line| line.contains("\"event\":\"shutdown\"")).unwrap();
+    assert_eq!(event, r#"{"action":"poweroff","event":"shutdown","reason":"temperature"}"#);
+    daemon.stop();
+}
+
+// Every cycle sends a temperature event, three of them make at least two
+// cycles after the first write.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn unchanged_speed_is_not_rewritten() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").start("unchanged-speed", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    let events = daemon.subscribe();
+    assert_eq!(events.filter(|line| line.contains("\"event\":\"temperature\"")).take(3).count(), 3);
+    assert_eq!(daemon.trace(), ["0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn unchanged_speed_is_refreshed() {
+    let mut daemon = Setup::new().set("delay_on_change = 1").i2c("refresh_interval = 1").start("refresh", 55);
+    wait_until(|| format!("no refresh in {:?}", daemon.trace()), || daemon.trace().len() >= 2);
+    assert_eq!(daemon.writes(), ["0x1a 80 32"]);
+    daemon.stop();
+}
+
+// Off a Pi there is neither the firmware mailbox nor rpi_volt to read the
+// throttling state from.
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn unreadable_throttling_is_not_throttled() {
+    let mut daemon = Setup::new().set("throttle_boost_speed = 100").start("throttle", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.stop();
+    assert_eq!(daemon.writes(), ["0x1a 80 32", "0x1a 80 00"]);
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn boot_boost_then_curve() {
+    let mut daemon = Setup::new().set("delay_on_change = 1\nboot_boost = { speed = 100, duration = 2 }").start("boot-boost", 55);
+    daemon.wait_for_writes(&["0x1a 80 64", "0x1a 80 32"]);
+    daemon.stop();
+}
+
+#[cfg(not(feature = "hardware"))]
+#[test]
+fn exit_fan_speed_on_termination() {
+    let mut daemon = Setup::new().set("exit_fan_speed = 100").start("exit-fan-speed", 55);
+    daemon.wait_for_writes(&["0x1a 80 32"]);
+    daemon.stop();
+    assert_eq!(daemon.writes(), ["0x1a 80 32", "0x1a 80 64"]);
+}
+
+#[cfg(feature = "hardware")]
+fn read_stub_register(register: u8) -> u8 {
+    let bus = std::env::var("ARGONONED_TEST_I2C_BUS").unwrap().parse::<u8>().unwrap();
+    let mut i2c_interface = rppal::i2c::I2c::with_bus(bus).unwrap();
+    i2c_interface.set_slave_address(0x1a).unwrap();
+    return i2c_interface.smbus_read_byte(register).unwrap();
+}
+
+#[cfg(feature = "hardware")]
+fn wait_for_stub_register(register: u8, expected: u8) {
+    wait_until(|| format!("expected {} in register {:#x}, got {}", expected, register, read_stub_register(register)), || read_stub_register(register) == expected);
+}
+
+#[cfg(feature = "hardware")]
+#[test]
+#[ignore]
+fn i2c_stub_protocols() {
+    let bus = std::env::var("ARGONONED_TEST_I2C_BUS").expect("ARGONONED_TEST_I2C_BUS is not set");
+    for (protocol, register) in [("registers", 0x80), ("raw", 0x00)] {
+        let mut daemon = Setup::new().protocol(protocol).env("ARGONONED_I2C_BUS", &bus).start("i2c-stub", 55);
+        wait_for_stub_register(register, 50);
+        assert_eq!(daemon.command("set-speed 100"), "ok\n100%\n");
+        wait_for_stub_register(register, 100);
+        daemon.stop();
+        assert_eq!(read_stub_register(register), 0);
+    }
+}